@@ -19,13 +19,37 @@ impl Demo {
         let rect1 = renderer.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 255, 255));
         let rect2 = renderer.create_rect(Pos(-1., -1.), Pos(0., 0.), RGBA(255, 0, 0, 255));
         let rect3 = renderer.create_rect(Pos(-0.5, -0.5), Pos(0.5, 0.5), RGBA(0, 0, 0, 64));
-        let text = renderer.create_text(Pos(0., 0.), 10, RGBA(0, 0, 0, 120));
+
+        // cargo run --example main -- path/to/font.ttf
+        let text = match std::env::args().nth(1) {
+            Some(path) => {
+                let font = Font::from_file(path).expect("load font");
+                let glyphs = font.shape("Hello world", 20.).positioned();
+                let font = renderer.add_font(font);
+
+                renderer.create_text(Pos(0., 0.), font, 20., &glyphs, RGBA(0, 0, 0, 120))
+            }
+            None => {
+                let font = renderer.add_font(BoxGlyphs);
+                let glyphs = (0..10).map(|i| PositionedGlyph { id: i, x: i as f32 * 12., y: 0., font: 0 }).collect::<Vec<_>>();
+
+                renderer.create_text(Pos(0., 0.), font, 20., &glyphs, RGBA(0, 0, 0, 120))
+            }
+        };
+
+        let image = renderer.create_image(Pos(-0.9, 0.4), Pos(-0.4, 0.9), ImageData {
+            width: 64,
+            height: 64,
+            stride: 64 * 4,
+            pixels: &checkerboard(64),
+        });
 
         renderer.set_display_list(&[
             DisplayItem::Rect(rect1),
             DisplayItem::Rect(rect2),
             DisplayItem::Rect(rect3),
             DisplayItem::Text(text),
+            DisplayItem::Image(image),
         ]);
 
         Self {
@@ -53,6 +77,9 @@ fn main() {
     let video = sdl.video().expect("init video");
     let mut event_pump = sdl.event_pump().expect("init event pump");
 
+    // for rounded clips
+    video.gl_attr().set_stencil_size(8);
+
     let window = video
         .window("Test", WIDTH, 900)
         .opengl()
@@ -105,6 +132,21 @@ fn main() {
     }
 }
 
+// no loading/decoding, just generate something
+fn checkerboard(size: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(size * size * 4);
+
+    for y in 0..size {
+        for x in 0..size {
+            let c = if (x / 8 + y / 8) % 2 == 0 { 255 } else { 0 };
+
+            pixels.extend_from_slice(&[c, c, c, 255]);
+        }
+    }
+
+    pixels
+}
+
 const WIDTH: u32 = 1200;
 
 #[cfg(target_os = "emscripten")]
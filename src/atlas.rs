@@ -0,0 +1,413 @@
+// many small images in few big textures so that they can be batched
+//
+// every page is a shelf packer: rows (shelves) of the same height,
+// each with a sorted list of free spans which are coalesced when
+// something is freed, fully-free shelves are merged together
+// and the ones at the end are given back to the page
+//
+// images bigger than the page get their own (dedicated) page which is
+// freed (with its texture) when the image is, the slot is reused later
+
+use super::backend::*;
+
+pub struct Atlas {
+    page_size: usize,
+    filter: TextureFilter,
+    pages: Vec<AtlasPage>,
+    // slots of freed pages
+    free_pages: Vec<usize>,
+    // textures of freed pages, deleted with the next upload()
+    deleted: Vec<TextureId>,
+}
+
+/// Where is the item stored, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtlasAlloc {
+    pub page: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Occupancy of the whole atlas, useful for tuning the page size
+#[derive(Clone, Debug)]
+pub struct AtlasStats {
+    pub page_size: usize,
+    pub pages: Vec<AtlasPageStats>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasPageStats {
+    pub width: usize,
+    pub height: usize,
+    pub allocations: usize,
+    // pixels
+    pub allocated: usize,
+    // how much of the height is taken by shelves
+    pub used_height: usize,
+}
+
+impl AtlasStats {
+    /// allocated / available pixels, over all pages
+    pub fn occupancy(&self) -> f32 {
+        let allocated: usize = self.pages.iter().map(|p| p.allocated).sum();
+        let capacity: usize = self.pages.iter().map(|p| p.width * p.height).sum();
+
+        if capacity == 0 {
+            return 0.;
+        }
+
+        allocated as f32 / capacity as f32
+    }
+}
+
+struct AtlasPage {
+    width: usize,
+    height: usize,
+    shelves: Vec<Shelf>,
+    allocations: usize,
+    allocated: usize,
+    // only one item, freed with it
+    dedicated: bool,
+
+    // created lazily, like buffers
+    texture: Option<TextureId>,
+    // (alloc, packed pixels) waiting for upload
+    pending: Vec<(AtlasAlloc, Vec<u8>)>,
+}
+
+// shelves are sorted by y & there are no holes between them
+struct Shelf {
+    y: usize,
+    height: usize,
+    // sorted by x & coalesced
+    free: Vec<Span>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Span {
+    x: usize,
+    width: usize,
+}
+
+// don't put small items into much taller shelves
+const MAX_SHELF_WASTE: f32 = 1.5;
+
+impl Atlas {
+    pub fn new(page_size: usize, filter: TextureFilter) -> Self {
+        Self {
+            page_size,
+            filter,
+            pages: Vec::new(),
+            free_pages: Vec::new(),
+            deleted: Vec::new(),
+        }
+    }
+
+    /// only the pages created from now on are affected
+    pub fn set_page_size(&mut self, page_size: usize) {
+        self.page_size = page_size;
+    }
+
+    /// empty items are not allowed (there would be nothing to find them by)
+    pub fn allocate(&mut self, width: usize, height: usize) -> AtlasAlloc {
+        assert!(width > 0 && height > 0, "empty allocation {}x{}", width, height);
+
+        if width > self.page_size || height > self.page_size {
+            let page = self.add_page(width, height, true);
+
+            return self.pages[page].allocate(page, width, height).expect("dedicated page");
+        }
+
+        for (i, p) in self.pages.iter_mut().enumerate() {
+            if !p.dedicated && p.width == self.page_size && p.height == self.page_size {
+                if let Some(alloc) = p.allocate(i, width, height) {
+                    return alloc;
+                }
+            }
+        }
+
+        let page = self.add_page(self.page_size, self.page_size, false);
+
+        self.pages[page].allocate(page, width, height).expect("empty page")
+    }
+
+    pub fn deallocate(&mut self, alloc: AtlasAlloc) {
+        let p = &mut self.pages[alloc.page];
+
+        p.deallocate(alloc);
+
+        if p.dedicated && p.allocations == 0 {
+            self.deleted.extend(p.texture);
+            self.pages[alloc.page] = AtlasPage::new(0, 0, true);
+            self.free_pages.push(alloc.page);
+        }
+    }
+
+    /// tightly packed RGBA8, uploaded with the next `upload()`
+    pub fn write(&mut self, alloc: AtlasAlloc, pixels: Vec<u8>) {
+        self.pages[alloc.page].pending.push((alloc, pixels));
+    }
+
+    /// uv of the top-left & bottom-right corners
+    pub fn uv(&self, alloc: AtlasAlloc) -> ((f32, f32), (f32, f32)) {
+        let p = &self.pages[alloc.page];
+        let (w, h) = (p.width as f32, p.height as f32);
+
+        (
+            (alloc.x as f32 / w, alloc.y as f32 / h),
+            ((alloc.x + alloc.width) as f32 / w, (alloc.y + alloc.height) as f32 / h),
+        )
+    }
+
+    pub fn texture(&self, page: usize) -> Option<TextureId> {
+        self.pages[page].texture
+    }
+
+    pub fn stats(&self) -> AtlasStats {
+        AtlasStats {
+            page_size: self.page_size,
+            pages: self.pages.iter().filter(|p| !p.is_free()).map(|p| AtlasPageStats {
+                width: p.width,
+                height: p.height,
+                allocations: p.allocations,
+                allocated: p.allocated,
+                used_height: p.shelves.last().map(|s| s.y + s.height).unwrap_or(0),
+            }).collect(),
+        }
+    }
+
+    pub fn upload(&mut self, backend: &mut impl Backend) {
+        let filter = self.filter;
+
+        for texture in self.deleted.drain(..) {
+            backend.delete_texture(texture);
+        }
+
+        for p in &mut self.pages {
+            if p.pending.is_empty() {
+                continue;
+            }
+
+            let texture = match p.texture {
+                Some(texture) => texture,
+                None => {
+                    let texture = backend.create_texture(filter);
+
+                    // allocate the whole page first so that we can upload just the parts
+                    backend.upload_texture(texture, p.width, p.height, &vec![0; 4 * p.width * p.height]);
+                    p.texture = Some(texture);
+
+                    texture
+                }
+            };
+
+            for (a, pixels) in p.pending.drain(..) {
+                backend.upload_texture_region(texture, a.x, a.y, a.width, a.height, &pixels);
+            }
+        }
+    }
+
+    fn add_page(&mut self, width: usize, height: usize, dedicated: bool) -> usize {
+        let page = AtlasPage::new(width, height, dedicated);
+
+        if let Some(i) = self.free_pages.pop() {
+            self.pages[i] = page;
+
+            return i;
+        }
+
+        self.pages.push(page);
+
+        self.pages.len() - 1
+    }
+}
+
+impl AtlasPage {
+    fn new(width: usize, height: usize, dedicated: bool) -> Self {
+        Self {
+            width,
+            height,
+            shelves: Vec::new(),
+            allocations: 0,
+            allocated: 0,
+            dedicated,
+            texture: None,
+            pending: Vec::new(),
+        }
+    }
+
+    // slot of a freed (dedicated) page
+    fn is_free(&self) -> bool {
+        self.width == 0
+    }
+
+    fn allocate(&mut self, page: usize, width: usize, height: usize) -> Option<AtlasAlloc> {
+        let shelf = self.find_shelf(width, height).or_else(|| self.add_shelf(height))?;
+        let s = &mut self.shelves[shelf];
+
+        // empty shelves can be split so the rest is not wasted
+        if s.is_empty(self.width) && s.height > height {
+            let rest = Shelf { y: s.y + height, height: s.height - height, free: vec![Span { x: 0, width: self.width }] };
+
+            s.height = height;
+            self.shelves.insert(shelf + 1, rest);
+        }
+
+        let s = &mut self.shelves[shelf];
+        let i = s.free.iter().position(|span| span.width >= width)?;
+        let x = s.free[i].x;
+
+        s.free[i].x += width;
+        s.free[i].width -= width;
+
+        if s.free[i].width == 0 {
+            s.free.remove(i);
+        }
+
+        self.allocations += 1;
+        self.allocated += width * height;
+
+        Some(AtlasAlloc { page, x, y: s.y, width, height })
+    }
+
+    // best (lowest) shelf which has enough space
+    fn find_shelf(&self, width: usize, height: usize) -> Option<usize> {
+        let max_height = (height as f32 * MAX_SHELF_WASTE).ceil() as usize;
+
+        self.shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.height >= height && (s.height <= max_height || s.is_empty(self.width)))
+            .filter(|(_, s)| s.free.iter().any(|span| span.width >= width))
+            .min_by_key(|(_, s)| s.height)
+            .map(|(i, _)| i)
+    }
+
+    fn add_shelf(&mut self, height: usize) -> Option<usize> {
+        let y = self.shelves.last().map(|s| s.y + s.height).unwrap_or(0);
+
+        if y + height > self.height {
+            return None;
+        }
+
+        self.shelves.push(Shelf { y, height, free: vec![Span { x: 0, width: self.width }] });
+
+        Some(self.shelves.len() - 1)
+    }
+
+    fn deallocate(&mut self, alloc: AtlasAlloc) {
+        let i = self.shelves.iter().position(|s| s.y == alloc.y).expect("invalid alloc");
+        let s = &mut self.shelves[i];
+
+        // insert & merge with neighbours
+        let at = s.free.iter().position(|span| span.x > alloc.x).unwrap_or(s.free.len());
+
+        s.free.insert(at, Span { x: alloc.x, width: alloc.width });
+
+        if at + 1 < s.free.len() && s.free[at].x + s.free[at].width == s.free[at + 1].x {
+            s.free[at].width += s.free[at + 1].width;
+            s.free.remove(at + 1);
+        }
+
+        if at > 0 && s.free[at - 1].x + s.free[at - 1].width == s.free[at].x {
+            s.free[at - 1].width += s.free[at].width;
+            s.free.remove(at);
+        }
+
+        self.allocations -= 1;
+        self.allocated -= alloc.width * alloc.height;
+
+        self.coalesce_shelves(i);
+    }
+
+    // merge empty shelf with its empty neighbours, give the last one back
+    fn coalesce_shelves(&mut self, mut i: usize) {
+        let width = self.width;
+
+        if !self.shelves[i].is_empty(width) {
+            return;
+        }
+
+        if i + 1 < self.shelves.len() && self.shelves[i + 1].is_empty(width) {
+            self.shelves[i].height += self.shelves[i + 1].height;
+            self.shelves.remove(i + 1);
+        }
+
+        if i > 0 && self.shelves[i - 1].is_empty(width) {
+            self.shelves[i - 1].height += self.shelves[i].height;
+            self.shelves.remove(i);
+            i -= 1;
+        }
+
+        if i == self.shelves.len() - 1 {
+            self.shelves.pop();
+        }
+    }
+}
+
+impl Shelf {
+    fn is_empty(&self, width: usize) -> bool {
+        self.free.len() == 1 && self.free[0].width == width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shelves() {
+        let mut atlas = Atlas::new(64, TextureFilter::Nearest);
+        let allocs = [(10, 8), (20, 8), (10, 12), (5, 5)].map(|(w, h)| atlas.allocate(w, h));
+
+        assert_eq!(allocs.map(|a| (a.x, a.y)), [(0, 0), (10, 0), (0, 8), (30, 0)]);
+        assert_eq!(atlas.stats().pages[0].used_height, 20);
+
+        for a in allocs {
+            atlas.deallocate(a);
+        }
+
+        let stats = atlas.stats();
+
+        assert_eq!((stats.pages[0].allocations, stats.pages[0].allocated, stats.pages[0].used_height), (0, 0, 0));
+    }
+
+    #[test]
+    fn dedicated_pages() {
+        let mut atlas = Atlas::new(64, TextureFilter::Nearest);
+        let mut backend = RecordingBackend::new();
+        let small = atlas.allocate(8, 8);
+
+        for _ in 0..3 {
+            let big = atlas.allocate(100, 80);
+
+            assert_eq!(big.page, 1);
+
+            atlas.write(big, vec![0; 4 * 100 * 80]);
+            atlas.upload(&mut backend);
+            atlas.deallocate(big);
+
+            assert_eq!(atlas.stats().pages.len(), 1);
+        }
+
+        atlas.upload(&mut backend);
+
+        let created = backend.calls.iter().filter(|c| matches!(c, Call::CreateTexture(..))).count();
+        let deleted = backend.calls.iter().filter(|c| matches!(c, Call::DeleteTexture(..))).count();
+
+        assert_eq!((created, deleted), (3, 3));
+
+        // regular pages are kept
+        atlas.deallocate(small);
+
+        assert_eq!(atlas.stats().pages.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty allocation")]
+    fn empty() {
+        Atlas::new(64, TextureFilter::Nearest).allocate(0, 0);
+    }
+}
@@ -0,0 +1,501 @@
+// everything which is needed to get batches on the screen
+//
+// `NotSureWhat` only builds buffers, indices & batches, the actual
+// submission is up to the backend so it can be GL, CPU or just a mock
+
+use super::{Pos, RGBA};
+
+/// Backend-specific handle of uploaded data
+pub type BufferId = u32;
+
+/// Backend-specific handle of RGBA8 texture
+pub type TextureId = u32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BufferKind {
+    Vertex,
+    Index,
+}
+
+/// How are textures sampled between texels (when scaled)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Linear,
+}
+
+/// Pipeline setup, each one knows its own vertex layout & shaders
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Program {
+    Rect,
+    /// rects with rounded corners, antialiased with a distance function,
+    /// needs `Uniform::Scale` (radii are in pixels)
+    RoundRect,
+    /// one side of a border (one quad for each), also needs `Uniform::Scale`
+    Border,
+    /// blurred (rounded) box, also needs `Uniform::Scale`
+    BoxShadow,
+    /// stops are in the texture, also needs `Uniform::Scale`
+    Gradient,
+    Image,
+    Text,
+    /// text with distance fields instead of coverage
+    SdfText,
+    /// lcd text is drawn in 2 passes (there's no dual-source blending in GLES 2),
+    /// this one darkens the destination by the per-channel coverage
+    LcdTextMask,
+    /// & this one adds the color (also per-channel)
+    LcdText,
+    /// color glyphs (emoji), only the alpha of `Uniform::Color` is used
+    ColorText,
+    /// `RoundRect` vertices but only the stencil is written,
+    /// pixels which are less than half covered are discarded
+    ClipMask,
+}
+
+/// Stencil test & update, it's used for rounded clips (nested ones have
+/// bigger values), the whole stencil is set to 0 by `clear()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stencil {
+    Disabled,
+    /// draw only where the stencil is equal to the value
+    Test(u8),
+    /// the same test & increment where it's drawn
+    Increment(u8),
+    /// the same test & decrement where it's drawn
+    Decrement(u8),
+}
+
+/// Per-batch parameters of the current program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Uniform {
+    /// added to every vertex position
+    Translate(Pos),
+    /// vertex position multiplier (before translation)
+    Scale(Pos),
+    Color(RGBA),
+    /// half-width of the antialiased edge of distance fields (in distance units)
+    Smoothing(f32),
+}
+
+pub trait Backend {
+    fn create_buffer(&mut self) -> BufferId;
+
+    fn delete_buffer(&mut self, buffer: BufferId);
+
+    /// replace whole buffer with new data
+    fn upload(&mut self, buffer: BufferId, kind: BufferKind, data: &[u8]);
+
+    /// replace part of the buffer (offset in bytes), it has to be big enough already
+    fn upload_range(&mut self, buffer: BufferId, kind: BufferKind, offset: usize, data: &[u8]);
+
+    fn create_texture(&mut self, filter: TextureFilter) -> TextureId;
+
+    fn delete_texture(&mut self, texture: TextureId);
+
+    /// replace whole texture with tightly packed RGBA8 pixels (top row first)
+    fn upload_texture(&mut self, texture: TextureId, width: usize, height: usize, pixels: &[u8]);
+
+    /// replace part of the texture, it has to be big enough already
+    fn upload_texture_region(&mut self, texture: TextureId, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]);
+
+    /// texture for the next draws (if the program needs one)
+    fn use_texture(&mut self, texture: TextureId);
+
+    fn use_program(&mut self, program: Program);
+
+    /// has to be called after `use_program()`
+    fn set_uniform(&mut self, uniform: Uniform);
+
+    /// draw `count` indices starting at `offset` (both in indices, not bytes)
+    /// with the last used program
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId, offset: usize, count: usize);
+
+    /// only pixels in (x, y, width, height) are drawn, y goes up from the bottom
+    /// (like in GL), None is the whole viewport
+    fn set_scissor(&mut self, rect: Option<[usize; 4]>);
+
+    fn set_stencil(&mut self, stencil: Stencil);
+
+    /// color & stencil, scissor should be disabled
+    fn clear(&mut self, color: [f32; 4]);
+
+    /// in pixels
+    fn viewport_size(&self) -> (usize, usize);
+}
+
+/// What was called on `RecordingBackend`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Call {
+    CreateBuffer(BufferId),
+    DeleteBuffer(BufferId),
+    Upload(BufferId, BufferKind, Vec<u8>),
+    UploadRange(BufferId, BufferKind, usize, Vec<u8>),
+    CreateTexture(TextureId, TextureFilter),
+    DeleteTexture(TextureId),
+    UploadTexture(TextureId, usize, usize, Vec<u8>),
+    UploadTextureRegion(TextureId, usize, usize, usize, usize, Vec<u8>),
+    UseTexture(TextureId),
+    UseProgram(Program),
+    SetUniform(Uniform),
+    DrawIndexed { vertices: BufferId, indices: BufferId, offset: usize, count: usize },
+    SetScissor(Option<[usize; 4]>),
+    SetStencil(Stencil),
+    Clear([f32; 4]),
+}
+
+/// Does nothing, just remembers what it was asked to do,
+/// useful for testing batching & index generation without any context
+pub struct RecordingBackend {
+    pub calls: Vec<Call>,
+    pub viewport: (usize, usize),
+    next_buffer: BufferId,
+    next_texture: TextureId,
+}
+
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self {
+            calls: Vec::new(),
+            viewport: (800, 600),
+            next_buffer: 1,
+            next_texture: 1,
+        }
+    }
+}
+
+impl Default for RecordingBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for RecordingBackend {
+    fn create_buffer(&mut self) -> BufferId {
+        let id = self.next_buffer;
+
+        self.next_buffer += 1;
+        self.calls.push(Call::CreateBuffer(id));
+
+        id
+    }
+
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        self.calls.push(Call::DeleteBuffer(buffer));
+    }
+
+    fn upload(&mut self, buffer: BufferId, kind: BufferKind, data: &[u8]) {
+        self.calls.push(Call::Upload(buffer, kind, data.to_vec()));
+    }
+
+    fn upload_range(&mut self, buffer: BufferId, kind: BufferKind, offset: usize, data: &[u8]) {
+        self.calls.push(Call::UploadRange(buffer, kind, offset, data.to_vec()));
+    }
+
+    fn create_texture(&mut self, filter: TextureFilter) -> TextureId {
+        let id = self.next_texture;
+
+        self.next_texture += 1;
+        self.calls.push(Call::CreateTexture(id, filter));
+
+        id
+    }
+
+    fn delete_texture(&mut self, texture: TextureId) {
+        self.calls.push(Call::DeleteTexture(texture));
+    }
+
+    fn upload_texture(&mut self, texture: TextureId, width: usize, height: usize, pixels: &[u8]) {
+        self.calls.push(Call::UploadTexture(texture, width, height, pixels.to_vec()));
+    }
+
+    fn upload_texture_region(&mut self, texture: TextureId, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]) {
+        self.calls.push(Call::UploadTextureRegion(texture, x, y, width, height, pixels.to_vec()));
+    }
+
+    fn use_texture(&mut self, texture: TextureId) {
+        self.calls.push(Call::UseTexture(texture));
+    }
+
+    fn use_program(&mut self, program: Program) {
+        self.calls.push(Call::UseProgram(program));
+    }
+
+    fn set_uniform(&mut self, uniform: Uniform) {
+        self.calls.push(Call::SetUniform(uniform));
+    }
+
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId, offset: usize, count: usize) {
+        self.calls.push(Call::DrawIndexed { vertices, indices, offset, count });
+    }
+
+    fn set_scissor(&mut self, rect: Option<[usize; 4]>) {
+        self.calls.push(Call::SetScissor(rect));
+    }
+
+    fn set_stencil(&mut self, stencil: Stencil) {
+        self.calls.push(Call::SetStencil(stencil));
+    }
+
+    fn clear(&mut self, color: [f32; 4]) {
+        self.calls.push(Call::Clear(color));
+    }
+
+    fn viewport_size(&self) -> (usize, usize) {
+        self.viewport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use crate::*;
+
+    fn draws(calls: &[Call]) -> Vec<&Call> {
+        calls.iter().filter(|c| matches!(c, Call::UseProgram(_) | Call::DrawIndexed { .. })).collect()
+    }
+
+    fn indices(calls: &[Call]) -> Vec<u16> {
+        let data = calls.iter().rev().find_map(|c| match c {
+            Call::Upload(_, BufferKind::Index, data) => Some(data),
+            _ => None,
+        });
+
+        data.unwrap().chunks(2).map(|b| u16::from_ne_bytes([b[0], b[1]])).collect()
+    }
+
+    fn uploads(calls: &[Call]) -> Vec<&Call> {
+        calls.iter().filter(|c| matches!(c, Call::Upload(..) | Call::UploadRange(..) | Call::UploadTexture(..) | Call::UploadTextureRegion(..))).collect()
+    }
+
+    #[test]
+    fn fused() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let rects = (0..3).map(|_| r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255))).collect::<Vec<_>>();
+
+        r.set_display_list(&rects.iter().map(|id| DisplayItem::Rect(*id)).collect::<Vec<_>>());
+        r.render();
+
+        let calls = &r.backend().calls;
+
+        assert_eq!(draws(calls), [
+            &Call::UseProgram(Program::Rect),
+            &Call::DrawIndexed { vertices: 1, indices: 2, offset: 0, count: 18 },
+        ]);
+    }
+
+    #[test]
+    fn broken() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let a = r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+        let b = r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+        let image = r.create_image(Pos(0., 0.), Pos(1., 1.), ImageData { width: 1, height: 1, stride: 4, pixels: &[0; 4] });
+
+        r.set_display_list(&[DisplayItem::Rect(a), DisplayItem::Image(image), DisplayItem::Rect(b)]);
+        r.render();
+
+        let calls = &r.backend().calls;
+        let (rects, images, index) = (1, 2, 3);
+
+        // different program, the next batch starts where the previous one ended
+        assert_eq!(draws(calls), [
+            &Call::UseProgram(Program::Rect),
+            &Call::DrawIndexed { vertices: rects, indices: index, offset: 0, count: 6 },
+            &Call::UseProgram(Program::Image),
+            &Call::DrawIndexed { vertices: images, indices: index, offset: 6, count: 6 },
+            &Call::UseProgram(Program::Rect),
+            &Call::DrawIndexed { vertices: rects, indices: index, offset: 12, count: 6 },
+        ]);
+    }
+
+    #[test]
+    fn pages() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let rects = (0..16385).map(|_| r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255))).collect::<Vec<_>>();
+
+        r.set_display_list(&rects.iter().map(|id| DisplayItem::Rect(*id)).collect::<Vec<_>>());
+        r.render();
+
+        let calls = &r.backend().calls;
+        let (page0, page1, index) = (1, 2, 3);
+
+        // one draw call per page, the last quad is the first one of the second page
+        assert_eq!(draws(calls), [
+            &Call::UseProgram(Program::Rect),
+            &Call::DrawIndexed { vertices: page0, indices: index, offset: 0, count: 6 * 16384 },
+            &Call::DrawIndexed { vertices: page1, indices: index, offset: 6 * 16384, count: 6 },
+        ]);
+
+        let indices = indices(calls);
+
+        assert_eq!(indices[..6 * 16384].iter().max(), Some(&u16::MAX));
+        assert_eq!(indices[6 * 16384..], [1, 0, 3, 0, 2, 3]);
+    }
+
+    #[test]
+    fn quad_indices() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let rects = (0..3).map(|_| r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255))).collect::<Vec<_>>();
+
+        // in the display list order, 4 vertices per quad
+        r.set_display_list(&[DisplayItem::Rect(rects[2]), DisplayItem::Rect(rects[0])]);
+        r.render();
+
+        assert_eq!(indices(&r.backend().calls), [9, 8, 11, 8, 10, 11, 1, 0, 3, 0, 2, 3]);
+
+        // the whole index buffer is replaced
+        r.set_display_list(&[DisplayItem::Rect(rects[1])]);
+        r.render();
+
+        assert_eq!(indices(&r.backend().calls), [5, 4, 7, 4, 6, 7]);
+    }
+
+    #[test]
+    fn text_changes() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let font = r.add_font(BoxGlyphs);
+        let glyphs = [PositionedGlyph { id: 0, x: 0., y: 0., font: 0 }];
+        let (black, red, blue) = (RGBA(0, 0, 0, 255), RGBA(255, 0, 0, 255), RGBA(0, 0, 255, 255));
+        let a = r.create_rich_text(Pos(0., 0.), &[TextSpan::new(font, 10., &glyphs, 10., black), TextSpan::new(font, 10., &glyphs, 10., black)]);
+        let b = r.create_text(Pos(0., 0.), font, 10., &glyphs, black);
+
+        r.set_display_list(&[DisplayItem::Text(a), DisplayItem::Text(b)]);
+        r.render();
+        r.backend_mut().calls.clear();
+
+        r.set_text_pos(a, Pos(0.5, 0.5));
+        r.set_span_color(a, 1, red);
+        r.set_text_color(b, blue);
+        r.render();
+
+        let calls = &r.backend().calls;
+        let uniforms = calls.iter().filter_map(|c| match c {
+            Call::SetUniform(u @ (Uniform::Translate(_) | Uniform::Color(_))) => Some(*u),
+            _ => None,
+        });
+
+        // just the uniforms of the affected texts & spans
+        assert_eq!(uploads(calls), Vec::<&Call>::new());
+        assert_eq!(uniforms.collect::<Vec<_>>(), [
+            Uniform::Translate(Pos(0.5, 0.5)),
+            Uniform::Color(black),
+            Uniform::Translate(Pos(0.5, 0.5)),
+            Uniform::Color(red),
+            Uniform::Translate(Pos(0., 0.)),
+            Uniform::Color(blue),
+        ]);
+    }
+
+    #[test]
+    fn image_data() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let a = r.create_image(Pos(0., 0.), Pos(1., 1.), ImageData { width: 2, height: 2, stride: 8, pixels: &[0; 16] });
+        let b = r.create_image(Pos(0., 0.), Pos(1., 1.), ImageData { width: 1, height: 1, stride: 4, pixels: &[0; 4] });
+
+        r.set_display_list(&[DisplayItem::Image(a), DisplayItem::Image(b)]);
+        r.render();
+        r.backend_mut().calls.clear();
+
+        // the same size, only the pixels
+        let alloc = r.images[a.0].alloc;
+
+        r.set_image_data(a, ImageData { width: 2, height: 2, stride: 8, pixels: &[255; 16] });
+        r.render();
+
+        assert_eq!(r.images[a.0].alloc, alloc);
+        assert!(matches!(uploads(&r.backend().calls)[..], [Call::UploadTextureRegion(_, x, y, 2, 2, pixels)] if (*x, *y) == (alloc.x, alloc.y) && pixels == &[255; 16]));
+
+        // another slot & new uvs of the quad
+        r.backend_mut().calls.clear();
+        r.set_image_data(a, ImageData { width: 3, height: 1, stride: 12, pixels: &[255; 12] });
+        r.render();
+
+        let realloc = r.images[a.0].alloc;
+
+        assert_ne!((realloc.x, realloc.y), (alloc.x, alloc.y));
+        assert!(matches!(uploads(&r.backend().calls)[..], [
+            Call::UploadRange(1, BufferKind::Vertex, 0, data),
+            Call::UploadTextureRegion(_, x, y, 3, 1, _),
+        ] if data.len() == mem::size_of::<Quad<Pos>>() && (*x, *y) == (realloc.x, realloc.y)), "{:?}", uploads(&r.backend().calls));
+    }
+
+    #[test]
+    fn appended() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let rects = (0..3).map(|_| r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255))).collect::<Vec<_>>();
+        let uploads = |r: &NotSureWhat<RecordingBackend>| r.backend().calls.iter().filter(|c| matches!(c, Call::Upload(1, ..) | Call::UploadRange(1, ..))).cloned().collect::<Vec<_>>();
+        let quad_size = mem::size_of::<Quad<RGBA>>();
+
+        r.set_display_list(&rects.iter().map(|id| DisplayItem::Rect(*id)).collect::<Vec<_>>());
+        r.render();
+
+        assert!(matches!(uploads(&r)[..], [Call::Upload(_, _, ref data)] if data.len() == 3 * quad_size));
+
+        // full, twice as big
+        r.backend_mut().calls.clear();
+        r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+        r.render();
+
+        assert!(matches!(uploads(&r)[..], [Call::Upload(_, _, ref data)] if data.len() == 6 * quad_size));
+
+        // so the next one is just appended
+        r.backend_mut().calls.clear();
+        r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+        r.render();
+
+        assert!(matches!(uploads(&r)[..], [Call::UploadRange(_, _, offset, ref data)] if offset == 4 * quad_size && data.len() == quad_size));
+    }
+
+    #[test]
+    fn clipped() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let a = r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+        let b = r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+        let clip = Clip::rect(Pos(-1., -1.), Pos(0., 0.));
+
+        r.set_display_list(&[
+            DisplayItem::Rect(a),
+            DisplayItem::PushClip(clip),
+            DisplayItem::Rect(a),
+            DisplayItem::Rect(b),
+            DisplayItem::PopClip,
+            // nothing is drawn in between
+            DisplayItem::PushClip(clip),
+            DisplayItem::PopClip,
+            DisplayItem::Rect(b),
+        ]);
+        r.render();
+
+        let calls = r.backend().calls.iter().filter(|c| matches!(c, Call::SetScissor(_) | Call::SetStencil(_) | Call::DrawIndexed { .. }));
+
+        // batches are broken only where the clip changes, scissor is in pixels (of 800x600)
+        assert_eq!(calls.collect::<Vec<_>>(), [
+            &Call::DrawIndexed { vertices: 1, indices: 2, offset: 0, count: 6 },
+            &Call::SetScissor(Some([0, 0, 400, 300])),
+            &Call::SetStencil(Stencil::Disabled),
+            &Call::DrawIndexed { vertices: 1, indices: 2, offset: 6, count: 12 },
+            &Call::SetScissor(None),
+            &Call::SetStencil(Stencil::Disabled),
+            &Call::DrawIndexed { vertices: 1, indices: 2, offset: 18, count: 6 },
+        ]);
+    }
+
+    #[test]
+    fn changes() {
+        let mut r = NotSureWhat::with_backend(RecordingBackend::new());
+        let rects = (0..3).map(|_| r.create_rect(Pos(0., 0.), Pos(1., 1.), RGBA(0, 0, 0, 255))).collect::<Vec<_>>();
+
+        r.set_display_list(&rects.iter().map(|id| DisplayItem::Rect(*id)).collect::<Vec<_>>());
+        r.render();
+        r.backend_mut().calls.clear();
+
+        r.set_rect_color(rects[1], RGBA(255, 0, 0, 255));
+        r.render();
+
+        // only the changed quad is uploaded & indices are not
+        let uploads = r.backend().calls.iter().filter(|c| matches!(c, Call::Upload(..) | Call::UploadRange(..))).collect::<Vec<_>>();
+
+        assert!(matches!(uploads[..], [Call::UploadRange(1, BufferKind::Vertex, offset, data)] if *offset == data.len()), "{:?}", uploads);
+    }
+}
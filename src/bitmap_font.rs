@@ -0,0 +1,520 @@
+// bitmap fonts (BDF, PSF1/PSF2), for tiny displays where every pixel matters
+//
+// glyphs are already bitmaps so they go to the atlas as they are (no outlines,
+// no antialiasing), other sizes are just integer multiples of the native one
+//
+// there's no shaping either, it's one glyph per char (in bidi order),
+// with fixed advances & no kerning
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use unicode_bidi::{BidiInfo, Level};
+
+use super::font::{FontError, FontMetrics};
+use super::glyphs::{GlyphBitmap, GlyphId, GlyphRasterizer};
+use super::layout::{self, LayoutOptions, Paragraph, Shaper};
+use super::shaping::{ShapedGlyph, ShapedText, TextMetrics};
+
+/// Parsed BDF/PSF font, cheap to clone
+#[derive(Clone)]
+pub struct BitmapFont {
+    glyphs: Arc<Vec<BitmapGlyph>>,
+    chars: Arc<HashMap<char, GlyphId>>,
+    // for chars which are not in the font
+    default_glyph: GlyphId,
+    // in pixels of the native size
+    ascent: i32,
+    descent: i32,
+}
+
+// coverage is 0 or 255, top row first
+#[derive(Clone, Debug, Default)]
+struct BitmapGlyph {
+    width: usize,
+    height: usize,
+    left: i32,
+    top: i32,
+    advance: i32,
+    coverage: Vec<u8>,
+}
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+// glyph ids are u16
+const MAX_GLYPHS: usize = GlyphId::MAX as usize + 1;
+
+impl BitmapFont {
+    /// BDF or PSF (not compressed), detected from the content
+    pub fn from_bytes(data: &[u8]) -> Result<Self, FontError> {
+        if data.starts_with(&PSF1_MAGIC) || data.starts_with(&PSF2_MAGIC) {
+            Self::from_psf(data)
+        } else {
+            Self::from_bdf(data)
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, FontError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Glyph Bitmap Distribution Format (X11 fonts)
+    pub fn from_bdf(data: &[u8]) -> Result<Self, FontError> {
+        let text = std::str::from_utf8(data).map_err(|_| FontError::Bitmap("BDF is not text"))?;
+        let mut lines = text.lines().map(str::trim);
+
+        if !lines.next().is_some_and(|l| l.starts_with("STARTFONT")) {
+            return Err(FontError::Bitmap("missing STARTFONT"));
+        }
+
+        let mut font = Self::empty();
+        let mut bbox_height = 0;
+        let mut default_char = None;
+        let mut glyphs = Vec::new();
+        let mut chars = HashMap::new();
+
+        while let Some(line) = lines.next() {
+            let (key, args) = parse_bdf_line(line);
+
+            match key {
+                "FONTBOUNDINGBOX" => bbox_height = args.get(1).copied().unwrap_or(0),
+                "FONT_ASCENT" => font.ascent = args.first().copied().unwrap_or(0),
+                "FONT_DESCENT" => font.descent = args.first().copied().unwrap_or(0),
+                "DEFAULT_CHAR" => default_char = args.first().copied(),
+                "STARTCHAR" => {
+                    let (encoding, glyph) = parse_bdf_char(&mut lines)?;
+
+                    if glyphs.len() == MAX_GLYPHS {
+                        return Err(FontError::Bitmap("too many glyphs"));
+                    }
+
+                    // -1 means no encoding, the glyph can't be reached by chars
+                    if let Some(ch) = char::from_u32(encoding as u32) {
+                        chars.insert(ch, glyphs.len() as GlyphId);
+                    }
+
+                    if Some(encoding) == default_char {
+                        font.default_glyph = glyphs.len() as GlyphId;
+                    }
+
+                    glyphs.push(glyph);
+                }
+                _ => {}
+            }
+        }
+
+        // ascent & descent are optional properties
+        if font.ascent == 0 && font.descent == 0 {
+            font.ascent = glyphs.iter().map(|g| g.top).max().unwrap_or(bbox_height);
+            font.descent = bbox_height - font.ascent;
+        }
+
+        // everything is a multiple of this
+        if font.ascent + font.descent <= 0 {
+            return Err(FontError::Bitmap("BDF has no size"));
+        }
+
+        if default_char.is_none() {
+            font.default_glyph = chars.get(&'?').copied().unwrap_or(0);
+        }
+
+        font.glyphs = Arc::new(glyphs);
+        font.chars = Arc::new(chars);
+
+        Ok(font)
+    }
+
+    /// PC Screen Font (Linux console), version 1 or 2
+    pub fn from_psf(data: &[u8]) -> Result<Self, FontError> {
+        let u32_at = |i: usize| data.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+
+        let (header_size, count, width, height, char_size, has_table, psf2) = if data.starts_with(&PSF2_MAGIC) {
+            let header = (8..32).step_by(4).map(u32_at).collect::<Option<Vec<_>>>().ok_or(FontError::Bitmap("truncated PSF header"))?;
+            let (header_size, flags, count, char_size, height, width) = (header[0], header[1], header[2], header[3], header[4], header[5]);
+
+            (header_size, count, width, height, char_size, flags & 1 != 0, true)
+        } else if data.starts_with(&PSF1_MAGIC) && data.len() >= 4 {
+            let (mode, char_size) = (data[2], data[3] as usize);
+            let count = if mode & 0x01 != 0 { 512 } else { 256 };
+
+            (4, count, 8, char_size, char_size, mode & 0x06 != 0, false)
+        } else {
+            return Err(FontError::Bitmap("not a PSF font"));
+        };
+
+        if count > MAX_GLYPHS {
+            return Err(FontError::Bitmap("too many glyphs"));
+        }
+
+        let row = width.div_ceil(8);
+
+        if width == 0 || height == 0 || char_size < row * height {
+            return Err(FontError::Bitmap("invalid PSF glyph size"));
+        }
+
+        let table_start = header_size + count * char_size;
+        let bitmaps = data.get(header_size..table_start).ok_or(FontError::Bitmap("truncated PSF glyphs"))?;
+
+        // no descent in the format, so the baseline is guessed like the console does
+        let descent = (height as i32 + 3) / 8;
+        let mut font = Self::empty();
+
+        font.ascent = height as i32 - descent;
+        font.descent = descent;
+        font.glyphs = Arc::new(bitmaps.chunks_exact(char_size).map(|bits| BitmapGlyph {
+            width,
+            height,
+            left: 0,
+            top: font.ascent,
+            advance: width as i32,
+            coverage: unpack_bits(bits, width, height),
+        }).collect());
+
+        let mut chars = HashMap::new();
+
+        if has_table {
+            let table = &data[table_start..];
+
+            if psf2 {
+                parse_psf2_table(table, count, &mut chars);
+            } else {
+                parse_psf1_table(table, count, &mut chars);
+            }
+        } else {
+            // no table means the glyph index is the char (latin-1 is close enough)
+            chars.extend((0..count.min(256)).map(|i| (i as u8 as char, i as GlyphId)));
+        }
+
+        font.default_glyph = chars.get(&'?').copied().unwrap_or(0);
+        font.chars = Arc::new(chars);
+
+        Ok(font)
+    }
+
+    fn empty() -> Self {
+        Self {
+            glyphs: Arc::new(Vec::new()),
+            chars: Arc::new(HashMap::new()),
+            default_glyph: 0,
+            ascent: 0,
+            descent: 0,
+        }
+    }
+
+    /// native size (ascent + descent) in pixels, `size` of the other methods
+    /// is rounded to a multiple of this one so that the pixels stay square
+    pub fn size(&self) -> f32 {
+        (self.ascent + self.descent) as f32
+    }
+
+    fn multiple(&self, size: f32) -> i32 {
+        (size / self.size()).round().max(1.) as i32
+    }
+
+    pub fn glyph_index(&self, ch: char) -> Option<GlyphId> {
+        self.chars.get(&ch).copied()
+    }
+
+    /// paragraph direction is taken from the first strong char
+    pub fn shape(&self, text: &str, size: f32) -> ShapedText {
+        self.shape_with_level(text, size, None)
+    }
+
+    pub(crate) fn shape_with_level(&self, text: &str, size: f32, level: Option<Level>) -> ShapedText {
+        let k = self.multiple(size) as f32;
+        let bidi = BidiInfo::new(text, level);
+        let mut pen = 0.;
+        let mut glyphs = Vec::with_capacity(text.len());
+
+        for para in &bidi.paragraphs {
+            let (levels, runs) = bidi.visual_runs(para, para.range.clone());
+
+            for run in runs {
+                let rtl = levels[run.start].is_rtl();
+                let mut chars: Vec<_> = text[run.clone()].char_indices().collect();
+
+                if rtl {
+                    chars.reverse();
+                }
+
+                for (i, ch) in chars {
+                    let id = self.glyph_index(ch).unwrap_or(self.default_glyph);
+                    let advance = self.glyphs.get(id as usize).map_or(0, |g| g.advance) as f32 * k;
+
+                    glyphs.push(ShapedGlyph { id, x: pen, y: 0., advance, cluster: run.start + i, rtl, font: 0 });
+
+                    pen += advance;
+                }
+            }
+        }
+
+        ShapedText { glyphs, width: pen }
+    }
+
+    /// see `Font::measure()`
+    pub fn measure(&self, text: &str, size: f32) -> TextMetrics {
+        let shaped = self.shape(text, size);
+        let metrics = GlyphRasterizer::metrics(self, size);
+
+        TextMetrics {
+            width: shaped.width,
+            ascent: metrics.ascent,
+            descent: metrics.descent,
+            line_gap: metrics.line_gap,
+            carets: shaped.carets(text),
+        }
+    }
+
+    /// see `Font::layout()`
+    pub fn layout(&self, text: &str, size: f32, options: &LayoutOptions) -> Paragraph {
+        layout::layout(self, text, size, options)
+    }
+}
+
+impl Shaper for BitmapFont {
+    fn shape_with_level(&self, text: &str, size: f32, level: Option<Level>) -> ShapedText {
+        BitmapFont::shape_with_level(self, text, size, level)
+    }
+
+    fn metrics(&self, size: f32) -> FontMetrics {
+        GlyphRasterizer::metrics(self, size)
+    }
+
+    fn has_char(&self, ch: char) -> bool {
+        self.glyph_index(ch).is_some()
+    }
+}
+
+impl GlyphRasterizer for BitmapFont {
+    // offset is ignored, advances are whole pixels anyway
+    fn rasterize(&self, glyph: GlyphId, size: f32, offset: f32) -> GlyphBitmap {
+        let g = match self.glyphs.get(glyph as usize) {
+            Some(g) => g,
+            None => return GlyphBitmap::default(),
+        };
+
+        let k = self.multiple(size) as usize;
+        let width = g.width * k;
+        let mut coverage = Vec::with_capacity(width * g.height * k);
+
+        // nearest neighbour
+        for row in g.coverage.chunks_exact(g.width.max(1)).take(g.height) {
+            let scaled: Vec<u8> = row.iter().flat_map(|&c| std::iter::repeat_n(c, k)).collect();
+
+            for _ in 0..k {
+                coverage.extend_from_slice(&scaled);
+            }
+        }
+
+        GlyphBitmap {
+            width,
+            height: g.height * k,
+            left: g.left * k as i32,
+            top: g.top * k as i32,
+            coverage,
+            color: None,
+        }
+    }
+
+    fn metrics(&self, size: f32) -> FontMetrics {
+        let k = self.multiple(size) as f32;
+
+        // whole pixels of the native size
+        FontMetrics {
+            ascent: self.ascent as f32 * k,
+            descent: -self.descent as f32 * k,
+            line_gap: 0.,
+            underline_position: -k,
+            underline_thickness: k,
+            strikethrough_position: (self.ascent as f32 / 3.).round() * k + k,
+            strikethrough_thickness: k,
+        }
+    }
+}
+
+// keyword & numbers after it (all of the properties we need are numbers)
+fn parse_bdf_line(line: &str) -> (&str, Vec<i32>) {
+    let mut words = line.split_whitespace();
+    let key = words.next().unwrap_or("");
+
+    (key, words.filter_map(|w| w.parse().ok()).collect())
+}
+
+// from after STARTCHAR to ENDCHAR, (encoding, glyph)
+fn parse_bdf_char<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Result<(i32, BitmapGlyph), FontError> {
+    let mut encoding = -1;
+    let mut glyph = BitmapGlyph::default();
+    let mut rows = Vec::new();
+    let mut in_bitmap = false;
+
+    for line in lines {
+        if line == "ENDCHAR" {
+            if rows.len() != glyph.height {
+                return Err(FontError::Bitmap("BDF bitmap doesn't match its BBX"));
+            }
+
+            for row in rows {
+                let bits = hex_bytes(row).ok_or(FontError::Bitmap("invalid BDF bitmap"))?;
+
+                if bits.len() * 8 < glyph.width {
+                    return Err(FontError::Bitmap("BDF bitmap row is too short"));
+                }
+
+                glyph.coverage.extend(unpack_bits(&bits, glyph.width, 1));
+            }
+
+            return Ok((encoding, glyph));
+        }
+
+        if in_bitmap {
+            rows.push(line);
+            continue;
+        }
+
+        let (key, args) = parse_bdf_line(line);
+
+        match (key, args.as_slice()) {
+            ("ENCODING", [e, ..]) => encoding = *e,
+            ("DWIDTH", [dx, ..]) => glyph.advance = *dx,
+            ("BBX", [w, h, x, y, ..]) => {
+                glyph.width = (*w).max(0) as usize;
+                glyph.height = (*h).max(0) as usize;
+                glyph.left = *x;
+                // y is the bottom edge
+                glyph.top = y + h;
+            }
+            ("BITMAP", _) => in_bitmap = true,
+            _ => {}
+        }
+    }
+
+    Err(FontError::Bitmap("missing ENDCHAR"))
+}
+
+fn hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// msb first, rows are padded to whole bytes
+fn unpack_bits(bits: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let row = width.div_ceil(8);
+    let mut coverage = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let byte = bits[y * row + x / 8];
+
+            coverage.push(if byte & (0x80 >> (x % 8)) != 0 { 255 } else { 0 });
+        }
+    }
+
+    coverage
+}
+
+// u16 chars for every glyph, terminated by 0xFFFF, sequences (after 0xFFFE) are skipped
+fn parse_psf1_table(table: &[u8], count: usize, chars: &mut HashMap<char, GlyphId>) {
+    let mut glyph = 0;
+    let mut in_sequences = false;
+
+    for pair in table.chunks_exact(2) {
+        if glyph == count {
+            break;
+        }
+
+        match u16::from_le_bytes([pair[0], pair[1]]) {
+            0xFFFF => {
+                glyph += 1;
+                in_sequences = false;
+            }
+            0xFFFE => in_sequences = true,
+            c if !in_sequences => {
+                if let Some(ch) = char::from_u32(c as u32) {
+                    chars.entry(ch).or_insert(glyph as GlyphId);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// UTF-8 chars for every glyph, terminated by 0xFF, sequences (after 0xFE) are skipped
+fn parse_psf2_table(table: &[u8], count: usize, chars: &mut HashMap<char, GlyphId>) {
+    for (glyph, entry) in table.split(|&b| b == 0xFF).take(count).enumerate() {
+        let singles = entry.split(|&b| b == 0xFE).next().unwrap_or(&[]);
+
+        if let Ok(s) = std::str::from_utf8(singles) {
+            for ch in s.chars() {
+                chars.entry(ch).or_insert(glyph as GlyphId);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 8px wide blocks for the chars, 8px ascent & 2px descent
+    pub(crate) fn font(chars: &str) -> BitmapFont {
+        BitmapFont::from_bdf(bdf(chars).as_bytes()).unwrap()
+    }
+
+    fn bdf(chars: &str) -> String {
+        let mut bdf = String::from("STARTFONT 2.1\nFONT_ASCENT 8\nFONT_DESCENT 2\n");
+
+        for ch in chars.chars() {
+            bdf += &format!("STARTCHAR {0}\nENCODING {0}\nDWIDTH 8 0\nBBX 8 10 0 -2\nBITMAP\n", ch as u32);
+            bdf += &"FF\n".repeat(10);
+            bdf += "ENDCHAR\n";
+        }
+
+        bdf + "ENDFONT\n"
+    }
+
+    fn psf2(count: u32) -> Vec<u8> {
+        let mut data = PSF2_MAGIC.to_vec();
+
+        // version, header size, flags, count, char size, height, width
+        for v in [0, 32, 0, count, 8, 8, 8] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+
+        data.resize(32 + count as usize * 8, 0xFF);
+        data
+    }
+
+    #[test]
+    fn psf() {
+        let font = BitmapFont::from_psf(&psf2(256)).unwrap();
+
+        assert_eq!(font.glyphs.len(), 256);
+        assert_eq!(font.glyph_index('A'), Some(65));
+        assert_eq!(font.rasterize(65, 16., 0.).coverage, vec![255; 16 * 16]);
+    }
+
+    #[test]
+    fn bdf_font() {
+        let font = font("ab");
+
+        assert_eq!(font.size(), 10.);
+        assert_eq!(font.glyph_index('b'), Some(1));
+        assert_eq!(font.glyph_index('c'), None);
+        assert_eq!(font.rasterize(1, 20., 0.).coverage, vec![255; 16 * 20]);
+    }
+
+    #[test]
+    fn no_size() {
+        assert!(matches!(BitmapFont::from_bdf(b"STARTFONT 2.1\nENDFONT\n"), Err(FontError::Bitmap("BDF has no size"))));
+    }
+
+    #[test]
+    fn too_many_glyphs() {
+        assert!(matches!(BitmapFont::from_psf(&psf2(70_000)), Err(FontError::Bitmap("too many glyphs"))));
+    }
+}
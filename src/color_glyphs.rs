@@ -0,0 +1,392 @@
+// color glyphs (emoji), they have their own colors & are not tinted
+//
+// there are 2 kinds of them:
+// - bitmaps (CBDT, sbix), PNGs in a few fixed sizes (strikes), the closest
+//   strike is decoded & box-filtered to the requested size
+// - layers (COLR), outlines filled with palette colors, these are rasterized
+//   like normal glyphs & composited (source-over) on the CPU
+//
+// COLRv1 gradients are filled with the average color of their stops and
+// composite modes are ignored, it's good enough for emoji at UI sizes
+
+use ttf_parser::colr::{ClipBox, CompositeMode, Paint, Painter};
+use ttf_parser::{Face, OutlineBuilder, RasterGlyphImage, RasterImageFormat, RgbaColor, Transform};
+
+use super::font::{Font, Outline, Raster};
+use super::glyphs::{GlyphBitmap, GlyphId};
+
+impl Font {
+    /// None for normal (outline) glyphs
+    pub(crate) fn rasterize_color(&self, glyph: GlyphId, size: f32) -> Option<GlyphBitmap> {
+        let face = self.face();
+        let id = ttf_parser::GlyphId(glyph);
+
+        if face.is_color_glyph(id) {
+            return paint_layers(face, id, self.scale(size));
+        }
+
+        let image = face.glyph_raster_image(id, size.ceil() as u16)?;
+
+        decode_bitmap(&image, size)
+    }
+}
+
+// position is in strike pixels (y up, bottom-left corner)
+fn decode_bitmap(image: &RasterGlyphImage, size: f32) -> Option<GlyphBitmap> {
+    let (width, height, rgba) = match image.format {
+        RasterImageFormat::PNG => decode_png(image.data)?,
+        RasterImageFormat::BitmapPremulBgra32 => {
+            let rgba = image.data.chunks_exact(4).flat_map(|p| unpremultiply([p[2], p[1], p[0], p[3]])).collect();
+
+            (image.width as usize, image.height as usize, rgba)
+        }
+        // monochrome/gray bitmaps are not color glyphs, the outline is used instead
+        _ => return None,
+    };
+
+    // broken fonts, the glyph is skipped
+    if width == 0 || height == 0 || rgba.len() < 4 * width * height {
+        return None;
+    }
+
+    let scale = size / image.pixels_per_em as f32;
+    let new_width = ((width as f32 * scale).round() as usize).max(1);
+    let new_height = ((height as f32 * scale).round() as usize).max(1);
+    let color = resize(&rgba, (width, height), (new_width, new_height));
+
+    Some(GlyphBitmap {
+        width: new_width,
+        height: new_height,
+        left: (image.x as f32 * scale).round() as i32,
+        top: ((image.y as f32 + height as f32) * scale).round() as i32,
+        coverage: color.chunks_exact(4).map(|p| p[3]).collect(),
+        color: Some(color),
+    })
+}
+
+fn decode_png(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+
+    // palette & 16-bit -> 8-bit channels
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().ok()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).ok()?;
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels[..info.buffer_size()].to_vec(),
+        png::ColorType::Rgb => pixels[..info.buffer_size()].chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels[..info.buffer_size()].chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels[..info.buffer_size()].iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+
+    Some((info.width as usize, info.height as usize, rgba))
+}
+
+fn unpremultiply(p: [u8; 4]) -> [u8; 4] {
+    if p[3] == 0 {
+        return [0; 4];
+    }
+
+    let f = |c: u8| (c as u32 * 255 / p[3] as u32).min(255) as u8;
+
+    [f(p[0]), f(p[1]), f(p[2]), p[3]]
+}
+
+// box filter (average of the covered pixels, premultiplied so that the
+// transparent ones don't darken the edges), strikes are usually much bigger
+fn resize(rgba: &[u8], from: (usize, usize), to: (usize, usize)) -> Vec<u8> {
+    if from == to {
+        return rgba.to_vec();
+    }
+
+    let (sx, sy) = (from.0 as f32 / to.0 as f32, from.1 as f32 / to.1 as f32);
+    let mut pixels = Vec::with_capacity(4 * to.0 * to.1);
+
+    for y in 0..to.1 {
+        let (y0, y1) = span(y, sy, from.1);
+
+        for x in 0..to.0 {
+            let (x0, x1) = span(x, sx, from.0);
+            let mut sum = [0.; 4];
+
+            for yy in y0..y1 {
+                for xx in x0..x1 {
+                    let p = &rgba[4 * (yy * from.0 + xx)..];
+                    let a = p[3] as f32;
+
+                    sum[0] += p[0] as f32 * a;
+                    sum[1] += p[1] as f32 * a;
+                    sum[2] += p[2] as f32 * a;
+                    sum[3] += a;
+                }
+            }
+
+            let n = ((x1 - x0) * (y1 - y0)) as f32;
+            let c = |v: f32| if sum[3] > 0. { (v / sum[3]).round() as u8 } else { 0 };
+
+            pixels.extend_from_slice(&[c(sum[0]), c(sum[1]), c(sum[2]), (sum[3] / n).round() as u8]);
+        }
+    }
+
+    pixels
+}
+
+// source pixels under the destination pixel i (at least one)
+fn span(i: usize, scale: f32, len: usize) -> (usize, usize) {
+    let start = ((i as f32 * scale) as usize).min(len - 1);
+    let end = (((i + 1) as f32 * scale).ceil() as usize).min(len).max(start + 1);
+
+    (start, end)
+}
+
+fn paint_layers(face: &Face, glyph: ttf_parser::GlyphId, scale: f32) -> Option<GlyphBitmap> {
+    let mut painter = LayerPainter {
+        face,
+        scale,
+        outline: Vec::new(),
+        clips: Vec::new(),
+        transforms: vec![IDENTITY],
+        fills: Vec::new(),
+    };
+
+    // palette 0 is the default one, foreground (text color) layers are black
+    face.paint_color_glyph(glyph, 0, RgbaColor::new(0, 0, 0, 255), &mut painter)?;
+
+    // whole pixels, like the normal glyphs
+    let bounds = painter.fills.iter().filter_map(|f| f.bounds()).reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))?;
+    let (left, bottom, right, top) = (bounds.0.floor(), bounds.1.floor(), bounds.2.ceil(), bounds.3.ceil());
+    let (width, height) = ((right - left) as usize, (top - bottom) as usize);
+
+    // premultiplied, composited back to front
+    let mut canvas = vec![[0f32; 4]; width * height];
+
+    for fill in &painter.fills {
+        let mut coverage = vec![1f32; width * height];
+
+        for path in &fill.paths {
+            let mut raster = Raster::new(width, height);
+
+            for (a, b) in path {
+                // y goes down in the bitmap
+                raster.line((a.0 - left, top - a.1), (b.0 - left, top - b.1));
+            }
+
+            for (c, r) in coverage.iter_mut().zip(raster.coverage()) {
+                *c *= r as f32 / 255.;
+            }
+        }
+
+        let [r, g, b, a] = fill.color;
+
+        for (dst, c) in canvas.iter_mut().zip(coverage) {
+            let a = a * c;
+
+            *dst = [r * a + dst[0] * (1. - a), g * a + dst[1] * (1. - a), b * a + dst[2] * (1. - a), a + dst[3] * (1. - a)];
+        }
+    }
+
+    let color: Vec<u8> = canvas
+        .iter()
+        .flat_map(|p| {
+            let c = |v: f32| if p[3] > 0. { (v / p[3] * 255.).round().min(255.) as u8 } else { 0 };
+
+            [c(p[0]), c(p[1]), c(p[2]), (p[3] * 255.).round() as u8]
+        })
+        .collect();
+
+    Some(GlyphBitmap {
+        width,
+        height,
+        left: left as i32,
+        top: top as i32,
+        coverage: color.chunks_exact(4).map(|p| p[3]).collect(),
+        color: Some(color),
+    })
+}
+
+type Path = Vec<((f32, f32), (f32, f32))>;
+
+// one paint, everything inside all of the paths (intersection)
+struct Fill {
+    paths: Vec<Path>,
+    // 0..1
+    color: [f32; 4],
+}
+
+impl Fill {
+    // (left, bottom, right, top) in pixels
+    fn bounds(&self) -> Option<(f32, f32, f32, f32)> {
+        let mut bounds = (f32::MIN, f32::MIN, f32::MAX, f32::MAX);
+
+        for path in &self.paths {
+            let points = path.iter().flat_map(|(a, b)| [*a, *b]);
+            let (l, b, r, t) = points.fold((f32::MAX, f32::MAX, f32::MIN, f32::MIN), |(l, b, r, t), p| (l.min(p.0), b.min(p.1), r.max(p.0), t.max(p.1)));
+
+            bounds = (bounds.0.max(l), bounds.1.max(b), bounds.2.min(r), bounds.3.min(t));
+        }
+
+        if bounds.0 < bounds.2 && bounds.1 < bounds.3 {
+            Some(bounds)
+        } else {
+            None
+        }
+    }
+}
+
+const IDENTITY: Transform = Transform { a: 1., b: 0., c: 0., d: 1., e: 0., f: 0. };
+
+// just collects the fills, they are rasterized when the bounds are known
+struct LayerPainter<'a> {
+    face: &'a Face<'a>,
+    scale: f32,
+    // last outlined glyph
+    outline: Path,
+    clips: Vec<Path>,
+    transforms: Vec<Transform>,
+    fills: Vec<Fill>,
+}
+
+impl LayerPainter<'_> {
+    fn transform(&self) -> Transform {
+        *self.transforms.last().unwrap()
+    }
+}
+
+impl<'a> Painter<'a> for LayerPainter<'_> {
+    fn outline_glyph(&mut self, glyph: ttf_parser::GlyphId) {
+        let mut outline = Outline::new(self.scale);
+
+        self.face.outline_glyph(glyph, &mut Transformed(&mut outline, self.transform()));
+        self.outline = outline.lines;
+    }
+
+    fn paint(&mut self, paint: Paint<'a>) {
+        let color = match paint {
+            Paint::Solid(c) => rgba(c),
+            Paint::LinearGradient(g) => average(g.stops(0, &[]).map(|s| s.color)),
+            Paint::RadialGradient(g) => average(g.stops(0, &[]).map(|s| s.color)),
+            Paint::SweepGradient(g) => average(g.stops(0, &[]).map(|s| s.color)),
+        };
+
+        // COLRv0 has no clips, it just fills the outline
+        let paths = if self.clips.is_empty() { vec![self.outline.clone()] } else { self.clips.clone() };
+
+        self.fills.push(Fill { paths, color });
+    }
+
+    fn push_clip(&mut self) {
+        self.clips.push(self.outline.clone());
+    }
+
+    fn push_clip_box(&mut self, clip: ClipBox) {
+        let mut outline = Outline::new(self.scale);
+        let mut builder = Transformed(&mut outline, self.transform());
+
+        builder.move_to(clip.x_min, clip.y_min);
+        builder.line_to(clip.x_max, clip.y_min);
+        builder.line_to(clip.x_max, clip.y_max);
+        builder.line_to(clip.x_min, clip.y_max);
+        builder.close();
+
+        self.clips.push(outline.lines);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    fn push_layer(&mut self, _mode: CompositeMode) {}
+
+    fn pop_layer(&mut self) {}
+
+    fn push_transform(&mut self, t: Transform) {
+        self.transforms.push(Transform::combine(self.transform(), t));
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+}
+
+fn rgba(c: RgbaColor) -> [f32; 4] {
+    [c.red as f32 / 255., c.green as f32 / 255., c.blue as f32 / 255., c.alpha as f32 / 255.]
+}
+
+fn average(colors: impl Iterator<Item = RgbaColor>) -> [f32; 4] {
+    let (sum, n) = colors.fold(([0.; 4], 0), |(s, n), c| {
+        let c = rgba(c);
+
+        ([s[0] + c[0], s[1] + c[1], s[2] + c[2], s[3] + c[3]], n + 1)
+    });
+
+    sum.map(|v| if n > 0 { v / n as f32 } else { 0. })
+}
+
+// applies the (font units) transform before the outline is scaled to pixels
+struct Transformed<'a>(&'a mut Outline, Transform);
+
+impl Transformed<'_> {
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let t = &self.1;
+
+        (t.a * x + t.c * y + t.e, t.b * x + t.d * y + t.f)
+    }
+}
+
+impl OutlineBuilder for Transformed<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.apply(x, y);
+
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.apply(x, y);
+
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let ((x1, y1), (x, y)) = (self.apply(x1, y1), self.apply(x, y));
+
+        self.0.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let ((x1, y1), (x2, y2), (x, y)) = (self.apply(x1, y1), self.apply(x2, y2), self.apply(x, y));
+
+        self.0.curve_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bgra(width: u16, height: u16, data: &[u8]) -> RasterGlyphImage<'_> {
+        RasterGlyphImage { x: 0, y: 0, width, height, pixels_per_em: 2, format: RasterImageFormat::BitmapPremulBgra32, data }
+    }
+
+    #[test]
+    fn bitmap() {
+        // premultiplied BGRA
+        let glyph = decode_bitmap(&bgra(2, 1, &[0, 0, 128, 128, 255, 0, 0, 255]), 2.).unwrap();
+
+        assert_eq!((glyph.width, glyph.height, glyph.top), (2, 1, 1));
+        assert_eq!(glyph.color.unwrap(), [255, 0, 0, 128, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn malformed_bitmap() {
+        assert!(decode_bitmap(&bgra(2, 2, &[255; 12]), 2.).is_none());
+        assert!(decode_bitmap(&bgra(0, 2, &[]), 2.).is_none());
+        assert!(decode_bitmap(&bgra(2, 0, &[]), 2.).is_none());
+    }
+}
@@ -0,0 +1,396 @@
+// TrueType/OpenType fonts
+//
+// tables (cmap, hmtx, glyf, CFF) are parsed by ttf-parser, the outlines
+// are flattened to lines here & rasterized by accumulating signed area
+// (like font-rs) so we get antialiased coverage without any supersampling
+//
+// color glyphs (emoji) are in color_glyphs.rs
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use ttf_parser::{Face, OutlineBuilder};
+
+use super::glyphs::{GlyphBitmap, GlyphId, GlyphRasterizer};
+
+/// Parsed TTF/OTF file, cheap to clone
+#[derive(Clone)]
+pub struct Font {
+    // parsed just once & shared by the clones, the data is never freed
+    // (fonts are loaded once & live as long as the app anyway)
+    face: Arc<rustybuzz::Face<'static>>,
+    units_per_em: f32,
+    // for chars this font doesn't have
+    pub(crate) fallback: Vec<Font>,
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(io::Error),
+    Parse(ttf_parser::FaceParsingError),
+    /// BDF/PSF which doesn't make sense
+    Bitmap(&'static str),
+}
+
+/// Vertical metrics in pixels, y goes up (so descent is negative)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+    /// top edge of the line
+    pub underline_position: f32,
+    pub underline_thickness: f32,
+    pub strikethrough_position: f32,
+    pub strikethrough_thickness: f32,
+}
+
+impl FontMetrics {
+    /// baseline to baseline
+    pub fn line_height(&self) -> f32 {
+        self.ascent - self.descent + self.line_gap
+    }
+
+    /// typical proportions, for fonts which don't say
+    pub fn approximate(size: f32) -> Self {
+        Self {
+            ascent: 0.8 * size,
+            descent: -0.2 * size,
+            line_gap: 0.,
+            underline_position: -0.075 * size,
+            underline_thickness: 0.05 * size,
+            strikethrough_position: 0.3 * size,
+            strikethrough_thickness: 0.05 * size,
+        }
+    }
+}
+
+impl Font {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, FontError> {
+        Self::from_collection(data, 0)
+    }
+
+    /// `index` of the face in a collection (.ttc), 0 for normal fonts
+    pub fn from_collection(data: Vec<u8>, index: u32) -> Result<Self, FontError> {
+        // checked before it's leaked
+        Face::parse(&data, index)?;
+
+        let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+        let face = rustybuzz::Face::from_face(Face::parse(data, index)?);
+        let units_per_em = face.units_per_em() as f32;
+
+        Ok(Self {
+            face: Arc::new(face),
+            units_per_em,
+            fallback: Vec::new(),
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, FontError> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    /// fonts which are tried in order (per run) when a char is missing in this one,
+    /// glyphs then say which one they came from, see `ShapedGlyph::font`
+    pub fn with_fallback(mut self, fonts: Vec<Font>) -> Self {
+        self.fallback = fonts;
+        self
+    }
+
+    /// this font & then the fallbacks, indexed by `ShapedGlyph::font`
+    pub(crate) fn chain(&self) -> impl Iterator<Item = &Font> {
+        std::iter::once(self).chain(&self.fallback)
+    }
+
+    /// None if the font doesn't have it (it's up to the caller whether to use .notdef)
+    pub fn glyph_index(&self, ch: char) -> Option<GlyphId> {
+        self.face().glyph_index(ch).map(|g| g.0)
+    }
+
+    /// horizontal advance in pixels
+    pub fn advance(&self, glyph: GlyphId, size: f32) -> f32 {
+        let advance = self.face().glyph_hor_advance(ttf_parser::GlyphId(glyph)).unwrap_or(0);
+
+        advance as f32 * self.scale(size)
+    }
+
+    pub fn metrics(&self, size: f32) -> FontMetrics {
+        let face = self.face();
+        let scale = self.scale(size);
+        let approximate = FontMetrics::approximate(size);
+        let underline = face.underline_metrics();
+        let strikethrough = face.strikeout_metrics();
+
+        FontMetrics {
+            ascent: face.ascender() as f32 * scale,
+            descent: face.descender() as f32 * scale,
+            line_gap: face.line_gap() as f32 * scale,
+            underline_position: underline.map_or(approximate.underline_position, |m| m.position as f32 * scale),
+            underline_thickness: underline.map_or(approximate.underline_thickness, |m| m.thickness as f32 * scale),
+            strikethrough_position: strikethrough.map_or(approximate.strikethrough_position, |m| m.position as f32 * scale),
+            strikethrough_thickness: strikethrough.map_or(approximate.strikethrough_thickness, |m| m.thickness as f32 * scale),
+        }
+    }
+
+    pub(crate) fn face(&self) -> &Face<'_> {
+        &self.face
+    }
+
+    // the same face, with the tables for shaping
+    pub(crate) fn shaper(&self) -> &rustybuzz::Face<'_> {
+        &self.face
+    }
+
+    pub(crate) fn scale(&self, size: f32) -> f32 {
+        size / self.units_per_em
+    }
+}
+
+impl GlyphRasterizer for Font {
+    fn rasterize(&self, glyph: GlyphId, size: f32, offset: f32) -> GlyphBitmap {
+        if let Some(bitmap) = self.rasterize_color(glyph, size) {
+            return bitmap;
+        }
+
+        let scale = self.scale(size);
+        let mut outline = Outline::new(scale);
+
+        let bbox = match self.face().outline_glyph(ttf_parser::GlyphId(glyph), &mut outline) {
+            Some(bbox) => bbox,
+            None => return GlyphBitmap::default(),
+        };
+
+        // whole pixels, so the pen position stays pixel-aligned
+        let left = (bbox.x_min as f32 * scale + offset).floor();
+        let right = (bbox.x_max as f32 * scale + offset).ceil();
+        let bottom = (bbox.y_min as f32 * scale).floor();
+        let top = (bbox.y_max as f32 * scale).ceil();
+
+        let mut raster = Raster::new((right - left) as usize, (top - bottom) as usize);
+
+        for (a, b) in outline.lines {
+            // y goes down in the bitmap
+            raster.line((a.0 + offset - left, top - a.1), (b.0 + offset - left, top - b.1));
+        }
+
+        GlyphBitmap {
+            width: raster.width,
+            height: raster.height,
+            left: left as i32,
+            top: top as i32,
+            coverage: raster.coverage(),
+            color: None,
+        }
+    }
+
+    fn fallback(&self, index: usize) -> Option<&dyn GlyphRasterizer> {
+        self.fallback.get(index).map(|f| f as &dyn GlyphRasterizer)
+    }
+
+    fn metrics(&self, size: f32) -> FontMetrics {
+        Font::metrics(self, size)
+    }
+}
+
+// flattens the outline into lines, in pixels (y up)
+pub(crate) struct Outline {
+    scale: f32,
+    pub(crate) lines: Vec<((f32, f32), (f32, f32))>,
+    start: (f32, f32),
+    last: (f32, f32),
+}
+
+impl Outline {
+    pub(crate) fn new(scale: f32) -> Self {
+        Self { scale, lines: Vec::new(), start: (0., 0.), last: (0., 0.) }
+    }
+
+    fn line(&mut self, p: (f32, f32)) {
+        self.lines.push((self.last, p));
+        self.last = p;
+    }
+
+    // number of lines for a curve, based on how much it deviates from a line
+    fn segments(dev: (f32, f32)) -> usize {
+        const TOLERANCE: f32 = 3.;
+
+        1 + (TOLERANCE * (dev.0 * dev.0 + dev.1 * dev.1)).sqrt().sqrt() as usize
+    }
+}
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = (x * self.scale, y * self.scale);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.line((x * self.scale, y * self.scale));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.last, (x1 * self.scale, y1 * self.scale), (x * self.scale, y * self.scale));
+        let n = Self::segments((p0.0 - 2. * p1.0 + p2.0, p0.1 - 2. * p1.1 + p2.1));
+
+        for i in 1..=n {
+            let t = i as f32 / n as f32;
+            let mt = 1. - t;
+
+            self.line((
+                mt * mt * p0.0 + 2. * mt * t * p1.0 + t * t * p2.0,
+                mt * mt * p0.1 + 2. * mt * t * p1.1 + t * t * p2.1,
+            ));
+        }
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let s = self.scale;
+        let (p0, p1, p2, p3) = (self.last, (x1 * s, y1 * s), (x2 * s, y2 * s), (x * s, y * s));
+
+        // bigger of the two second differences
+        let d0 = (p0.0 - 2. * p1.0 + p2.0, p0.1 - 2. * p1.1 + p2.1);
+        let d1 = (p1.0 - 2. * p2.0 + p3.0, p1.1 - 2. * p2.1 + p3.1);
+        let n = Self::segments(if d0.0.hypot(d0.1) > d1.0.hypot(d1.1) { d0 } else { d1 });
+
+        for i in 1..=n {
+            let t = i as f32 / n as f32;
+            let mt = 1. - t;
+            let (a, b, c, d) = (mt * mt * mt, 3. * mt * mt * t, 3. * mt * t * t, t * t * t);
+
+            self.line((
+                a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+                a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+            ));
+        }
+    }
+
+    fn close(&mut self) {
+        if self.last != self.start {
+            self.line(self.start);
+        }
+    }
+}
+
+// every line adds its signed area to the cells it crosses, coverage is then
+// just a running sum (non-zero winding, clamped)
+pub(crate) struct Raster {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    // lines touching the right edge spill into the next cell
+    acc: Vec<f32>,
+}
+
+impl Raster {
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            acc: vec![0.; width * height + 2],
+        }
+    }
+
+    pub(crate) fn line(&mut self, a: (f32, f32), b: (f32, f32)) {
+        if a.1 == b.1 {
+            return;
+        }
+
+        let (dir, a, b) = if a.1 < b.1 { (1., a, b) } else { (-1., b, a) };
+        let dxdy = (b.0 - a.0) / (b.1 - a.1);
+        let w = self.width as f32;
+
+        let mut x = a.0;
+
+        if a.1 < 0. {
+            x -= a.1 * dxdy;
+        }
+
+        for y in (a.1.max(0.) as usize)..self.height.min(b.1.ceil() as usize) {
+            let row = y * self.width;
+            let dy = ((y + 1) as f32).min(b.1) - (y as f32).max(a.1);
+            let x_next = x + dxdy * dy;
+            let d = dy * dir;
+
+            // rounding errors could get us out of the row
+            let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+            let (x0, x1) = (x0.max(0.).min(w), x1.max(0.).min(w));
+            let (x0_floor, x1_ceil) = (x0.floor(), x1.ceil());
+            let (x0i, x1i) = (x0_floor as usize, x1_ceil as usize);
+
+            if x1i <= x0i + 1 {
+                // within one cell
+                let xm = 0.5 * (x0 + x1) - x0_floor;
+
+                self.acc[row + x0i] += d - d * xm;
+                self.acc[row + x0i + 1] += d * xm;
+            } else {
+                let s = 1. / (x1 - x0);
+                let x0f = x0 - x0_floor;
+                let a0 = 0.5 * s * (1. - x0f) * (1. - x0f);
+                let x1f = x1 - x1_ceil + 1.;
+                let am = 0.5 * s * x1f * x1f;
+
+                self.acc[row + x0i] += d * a0;
+
+                if x1i == x0i + 2 {
+                    self.acc[row + x0i + 1] += d * (1. - a0 - am);
+                } else {
+                    let a1 = s * (1.5 - x0f);
+
+                    self.acc[row + x0i + 1] += d * (a1 - a0);
+
+                    for xi in x0i + 2..x1i - 1 {
+                        self.acc[row + xi] += d * s;
+                    }
+
+                    let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+
+                    self.acc[row + x1i - 1] += d * (1. - a2 - am);
+                }
+
+                self.acc[row + x1i] += d * am;
+            }
+
+            x = x_next;
+        }
+    }
+
+    pub(crate) fn coverage(&self) -> Vec<u8> {
+        let mut sum = 0.;
+
+        self.acc[..self.width * self.height]
+            .iter()
+            .map(|a| {
+                sum += a;
+
+                (sum.abs().min(1.) * 255.).round() as u8
+            })
+            .collect()
+    }
+}
+
+impl From<io::Error> for FontError {
+    fn from(err: io::Error) -> Self {
+        FontError::Io(err)
+    }
+}
+
+impl From<ttf_parser::FaceParsingError> for FontError {
+    fn from(err: ttf_parser::FaceParsingError) -> Self {
+        FontError::Parse(err)
+    }
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::Io(err) => write!(f, "can't read font: {}", err),
+            FontError::Parse(err) => write!(f, "invalid font: {}", err),
+            FontError::Bitmap(err) => write!(f, "invalid bitmap font: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
@@ -0,0 +1,1134 @@
+// the "real" backend, GLES 2 / WebGL compatible subset of GL
+
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+
+use gl::types::*;
+
+use super::{BorderEdge, GradientData, Pos, RGBA, RoundRect, Shadow, Vertex, VertexIndex};
+use super::backend::*;
+
+pub struct GlBackend {
+    rect_program: GlProgram,
+    round_rect_program: GlProgram,
+    border_program: GlProgram,
+    box_shadow_program: GlProgram,
+    gradient_program: GlProgram,
+    image_program: GlProgram,
+    text_program: GlProgram,
+    sdf_text_program: GlProgram,
+    lcd_text_mask_program: GlProgram,
+    lcd_text_program: GlProgram,
+    color_text_program: GlProgram,
+    clip_mask_program: GlProgram,
+
+    current: Program,
+    // how many vertex attributes are enabled
+    attributes: usize,
+}
+
+struct GlProgram {
+    id: GLuint,
+
+    // uniform locations, -1 if not used by the program
+    u_pos: GLint,
+    u_scale: GLint,
+    u_color: GLint,
+    u_smoothing: GLint,
+}
+
+impl GlProgram {
+    unsafe fn new(vertex_shader_source: &str, fragment_shader_source: &str) -> Self {
+        let id = shader_program(vertex_shader_source, fragment_shader_source);
+
+        Self {
+            id,
+            u_pos: uniform_location(id, "u_pos"),
+            u_scale: uniform_location(id, "u_scale"),
+            u_color: uniform_location(id, "u_color"),
+            u_smoothing: uniform_location(id, "u_smoothing"),
+        }
+    }
+
+    // with the common functions of the rounded shapes
+    unsafe fn round_rect(vertex_shader_source: &str, fragment_shader_source: &str) -> Self {
+        Self::new(
+            &format!("{}{}", ROUND_RECT_COMMON_VS, vertex_shader_source),
+            &format!("{}{}", ROUND_RECT_COMMON_FS, fragment_shader_source),
+        )
+    }
+}
+
+impl GlBackend {
+    /// context has to be current already
+    pub fn new() -> Self {
+        unsafe {
+            // not used but webgl & opengl core profile require it
+            let mut vao = 0;
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+
+            check();
+
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::BlendEquation(gl::FUNC_ADD);
+
+            check();
+
+            Self {
+                rect_program: GlProgram::new(RECT_VS, RECT_FS),
+                round_rect_program: GlProgram::round_rect(ROUND_RECT_VS, ROUND_RECT_FS),
+                border_program: GlProgram::round_rect(BORDER_VS, BORDER_FS),
+                box_shadow_program: GlProgram::round_rect(BOX_SHADOW_VS, BOX_SHADOW_FS),
+                gradient_program: GlProgram::new(GRADIENT_VS, GRADIENT_FS),
+                image_program: GlProgram::new(IMAGE_VS, IMAGE_FS),
+                text_program: GlProgram::new(TEXT_VS, TEXT_FS),
+                sdf_text_program: GlProgram::new(TEXT_VS, SDF_TEXT_FS),
+                lcd_text_mask_program: GlProgram::new(TEXT_VS, LCD_TEXT_MASK_FS),
+                lcd_text_program: GlProgram::new(TEXT_VS, LCD_TEXT_FS),
+                color_text_program: GlProgram::new(TEXT_VS, COLOR_TEXT_FS),
+                clip_mask_program: GlProgram::round_rect(ROUND_RECT_VS, CLIP_MASK_FS),
+
+                current: Program::Rect,
+                attributes: 0,
+            }
+        }
+    }
+}
+
+impl Default for GlBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GlBackend {
+    fn program(&self) -> &GlProgram {
+        match self.current {
+            Program::Rect => &self.rect_program,
+            Program::RoundRect => &self.round_rect_program,
+            Program::Border => &self.border_program,
+            Program::BoxShadow => &self.box_shadow_program,
+            Program::Gradient => &self.gradient_program,
+            Program::Image => &self.image_program,
+            Program::Text => &self.text_program,
+            Program::SdfText => &self.sdf_text_program,
+            Program::LcdTextMask => &self.lcd_text_mask_program,
+            Program::LcdText => &self.lcd_text_program,
+            Program::ColorText => &self.color_text_program,
+            Program::ClipMask => &self.clip_mask_program,
+        }
+    }
+}
+
+impl Backend for GlBackend {
+    fn create_buffer(&mut self) -> BufferId {
+        let mut vbo = 0;
+
+        unsafe { gl::GenBuffers(1, &mut vbo) }
+
+        vbo
+    }
+
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        unsafe { gl::DeleteBuffers(1, &buffer) }
+    }
+
+    fn upload(&mut self, buffer: BufferId, kind: BufferKind, data: &[u8]) {
+        let target = target(kind);
+
+        unsafe {
+            gl::BindBuffer(target, buffer);
+            gl::BufferData(target, data.len() as GLsizeiptr, data.as_ptr() as *const GLvoid, gl::STATIC_DRAW);
+
+            check();
+        }
+    }
+
+    fn upload_range(&mut self, buffer: BufferId, kind: BufferKind, offset: usize, data: &[u8]) {
+        let target = target(kind);
+
+        unsafe {
+            gl::BindBuffer(target, buffer);
+            gl::BufferSubData(target, offset as GLintptr, data.len() as GLsizeiptr, data.as_ptr() as *const GLvoid);
+
+            check();
+        }
+    }
+
+    fn create_texture(&mut self, filter: TextureFilter) -> TextureId {
+        let filter = match filter {
+            TextureFilter::Nearest => gl::NEAREST,
+            TextureFilter::Linear => gl::LINEAR,
+        };
+        let mut texture = 0;
+
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+
+            // NPOT textures are fine in GLES 2 only without mipmaps & repeat
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+
+            check();
+        }
+
+        texture
+    }
+
+    fn delete_texture(&mut self, texture: TextureId) {
+        unsafe { gl::DeleteTextures(1, &texture) }
+    }
+
+    fn upload_texture(&mut self, texture: TextureId, width: usize, height: usize, pixels: &[u8]) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA as GLint,
+                width as GLsizei,
+                height as GLsizei,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const GLvoid,
+            );
+
+            check();
+        }
+    }
+
+    fn upload_texture_region(&mut self, texture: TextureId, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                x as GLint,
+                y as GLint,
+                width as GLsizei,
+                height as GLsizei,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const GLvoid,
+            );
+
+            check();
+        }
+    }
+
+    fn use_texture(&mut self, texture: TextureId) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+        }
+    }
+
+    fn use_program(&mut self, program: Program) {
+        self.current = program;
+
+        unsafe {
+            gl::UseProgram(self.program().id);
+
+            // dst * (1 - coverage * alpha) + color * coverage * alpha, for each channel
+            match program {
+                Program::LcdTextMask => gl::BlendFunc(gl::ZERO, gl::ONE_MINUS_SRC_COLOR),
+                Program::LcdText => gl::BlendFunc(gl::ONE, gl::ONE),
+                _ => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+            }
+
+            let color = if program == Program::ClipMask { gl::FALSE } else { gl::TRUE };
+
+            gl::ColorMask(color, color, color, color);
+        }
+    }
+
+    fn set_uniform(&mut self, uniform: Uniform) {
+        let program = self.program();
+
+        unsafe {
+            match uniform {
+                Uniform::Translate(pos) => gl::Uniform2f(program.u_pos, pos.0, pos.1),
+                Uniform::Scale(scale) => gl::Uniform2f(program.u_scale, scale.0, scale.1),
+                Uniform::Color(c) => gl::Uniform4f(program.u_color, c.0 as f32, c.1 as f32, c.2 as f32, c.3 as f32),
+                Uniform::Smoothing(s) => gl::Uniform1f(program.u_smoothing, s),
+            }
+
+            check();
+        }
+    }
+
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId, offset: usize, count: usize) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, vertices);
+
+            let (stride, attributes) = vertex_layout(self.current);
+
+            for (i, &(size, kind, offset)) in attributes.iter().enumerate() {
+                gl::EnableVertexAttribArray(i as GLuint);
+                gl::VertexAttribPointer(
+                    i as GLuint,
+                    size,
+                    kind,
+                    gl::FALSE,
+                    stride as GLint,
+                    offset as *const GLvoid,
+                );
+            }
+
+            // the rest would still point to the previous buffer
+            for i in attributes.len()..self.attributes {
+                gl::DisableVertexAttribArray(i as GLuint);
+            }
+
+            self.attributes = attributes.len();
+
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, indices);
+            gl::DrawElements(gl::TRIANGLES, count as i32, gl::UNSIGNED_SHORT, (offset * mem::size_of::<VertexIndex>()) as *const std::ffi::c_void);
+
+            check();
+        }
+    }
+
+    fn set_scissor(&mut self, rect: Option<[usize; 4]>) {
+        unsafe {
+            match rect {
+                Some([x, y, width, height]) => {
+                    gl::Enable(gl::SCISSOR_TEST);
+                    gl::Scissor(x as GLint, y as GLint, width as GLsizei, height as GLsizei);
+                }
+                None => gl::Disable(gl::SCISSOR_TEST),
+            }
+        }
+    }
+
+    fn set_stencil(&mut self, stencil: Stencil) {
+        let (value, op) = match stencil {
+            Stencil::Disabled => return unsafe { gl::Disable(gl::STENCIL_TEST) },
+            Stencil::Test(value) => (value, gl::KEEP),
+            Stencil::Increment(value) => (value, gl::INCR),
+            Stencil::Decrement(value) => (value, gl::DECR),
+        };
+
+        unsafe {
+            gl::Enable(gl::STENCIL_TEST);
+            gl::StencilFunc(gl::EQUAL, value as GLint, 0xff);
+            gl::StencilOp(gl::KEEP, gl::KEEP, op);
+        }
+    }
+
+    fn clear(&mut self, color: [f32; 4]) {
+        unsafe {
+            // the last draw could be a clip mask
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::ClearColor(color[0], color[1], color[2], color[3]);
+            gl::ClearStencil(0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        }
+    }
+
+    fn viewport_size(&self) -> (usize, usize) {
+        let mut viewport = [0; 4];
+
+        unsafe { gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr()) }
+
+        (viewport[2] as usize, viewport[3] as usize)
+    }
+}
+
+// (stride, [(components, type, offset)]), locations are in the order of ATTRIBUTES
+fn vertex_layout(program: Program) -> (usize, &'static [(GLint, GLenum, usize)]) {
+    // a_pos is always first, then color or uv
+    const POS: (GLint, GLenum, usize) = (2, gl::FLOAT, 0);
+    const DATA: usize = mem::size_of::<Pos>();
+
+    match program {
+        Program::Rect => (mem::size_of::<Vertex<RGBA>>(), &[POS, (4, gl::UNSIGNED_BYTE, DATA)]),
+        // see RoundRect
+        Program::RoundRect => (
+            mem::size_of::<Vertex<RoundRect>>(),
+            &[POS, (4, gl::UNSIGNED_BYTE, DATA), (4, gl::FLOAT, DATA + 4), (4, gl::FLOAT, DATA + 20), (4, gl::FLOAT, DATA + 36)],
+        ),
+        Program::ClipMask => vertex_layout(Program::RoundRect),
+        // starts like RoundRect, 7 attributes (raspi can do only 8)
+        Program::Border => (mem::size_of::<Vertex<BorderEdge>>(), EXTENDED_ROUND_RECT),
+        // the same layout as BorderEdge
+        Program::BoxShadow => (mem::size_of::<Vertex<Shadow>>(), EXTENDED_ROUND_RECT),
+        Program::Gradient => (
+            mem::size_of::<Vertex<GradientData>>(),
+            &[POS, (4, gl::UNSIGNED_BYTE, DATA), (4, gl::FLOAT, DATA + 4), (4, gl::FLOAT, DATA + 20), (4, gl::FLOAT, DATA + 36)],
+        ),
+        _ => (mem::size_of::<Vertex<Pos>>(), &[POS, (2, gl::FLOAT, DATA)]),
+    }
+}
+
+// RoundRect + vec4 + 4 bytes
+const EXTENDED_ROUND_RECT: &[(GLint, GLenum, usize)] = &[
+    (2, gl::FLOAT, 0),
+    (4, gl::UNSIGNED_BYTE, 8),
+    (4, gl::FLOAT, 12),
+    (4, gl::FLOAT, 28),
+    (4, gl::FLOAT, 44),
+    (4, gl::FLOAT, 60),
+    (4, gl::UNSIGNED_BYTE, 76),
+];
+
+// so that we don't need to query them, unused ones are ignored
+// (the same location can have more names, programs use just one of them)
+const ATTRIBUTES: &[&[&str]] = &[
+    &["a_pos"],
+    &["a_color", "a_uv", "a_gradient"],
+    &["a_bounds"],
+    &["a_radii_x", "a_ramp"],
+    &["a_radii_y", "a_geometry"],
+    &["a_widths", "a_shadow"],
+    &["a_side", "a_inset"],
+];
+
+fn target(kind: BufferKind) -> GLenum {
+    match kind {
+        BufferKind::Vertex => gl::ARRAY_BUFFER,
+        BufferKind::Index => gl::ELEMENT_ARRAY_BUFFER,
+    }
+}
+
+const RECT_VS: &str = r#"
+  #version 100
+
+  attribute vec2 a_pos;
+  attribute vec4 a_color;
+
+  varying vec4 v_color;
+
+  void main() {
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+    v_color = a_color;
+  }
+"#;
+
+const RECT_FS: &str = r#"
+  #version 100
+
+  precision mediump float;
+
+  varying vec4 v_color;
+
+  void main() {
+    gl_FragColor = v_color / 256.;
+  }
+"#;
+
+// shared by the rounded shapes, prepended to their sources (it has to start
+// with the version)
+const ROUND_RECT_COMMON_VS: &str = r#"
+  #version 100
+
+  // radii which don't fit are scaled down, all of them by the same factor
+  float fit_radii(vec2 size, vec4 rx, vec4 ry) {
+    return min(
+      min(size.x / max(rx.x + rx.y, size.x), size.x / max(rx.w + rx.z, size.x)),
+      min(size.y / max(ry.x + ry.w, size.y), size.y / max(ry.y + ry.z, size.y))
+    );
+  }
+"#;
+
+// pixel coords don't fit into mediump (raspi has highp anyway)
+const ROUND_RECT_COMMON_FS: &str = r#"
+  #version 100
+
+  #ifdef GL_FRAGMENT_PRECISION_HIGH
+    precision highp float;
+  #else
+    precision mediump float;
+  #endif
+
+  // negative inside, it's exact except for the ellipses where
+  // it's the implicit function divided by its gradient (good enough near the edge)
+  float edge_distance(vec2 p, vec2 half_size, vec2 r) {
+    vec2 d = abs(p) - half_size;
+    vec2 q = d + r;
+
+    if (r.x > 0. && r.y > 0. && q.x > 0. && q.y > 0.) {
+      vec2 n = q / r;
+
+      return (dot(n, n) - 1.) / (2. * length(n / r));
+    }
+
+    return length(max(d, 0.)) + min(max(d.x, d.y), 0.);
+  }
+
+  // with the radii of the corner p is in (tl, tr, br, bl)
+  float edge_distance(vec2 p, vec2 half_size, vec4 rx, vec4 ry) {
+    vec2 r = p.x < 0.
+      ? (p.y > 0. ? vec2(rx.x, ry.x) : vec2(rx.w, ry.w))
+      : (p.y > 0. ? vec2(rx.y, ry.y) : vec2(rx.z, ry.z));
+
+    return edge_distance(p, half_size, r);
+  }
+"#;
+
+// rect (bounds) & radii are the same in all 4 vertices, the distance to the edge
+// is computed in pixels relative to the center (y still goes up)
+const ROUND_RECT_VS: &str = r#"
+  uniform vec2 u_scale;
+
+  attribute vec2 a_pos;
+  attribute vec4 a_color;
+  attribute vec4 a_bounds;
+  // tl, tr, br, bl
+  attribute vec4 a_radii_x;
+  attribute vec4 a_radii_y;
+
+  varying vec4 v_color;
+  varying vec2 v_local;
+  varying vec2 v_half_size;
+  varying vec4 v_radii_x;
+  varying vec4 v_radii_y;
+
+  void main() {
+    vec2 center = (a_bounds.xy + a_bounds.zw) / 2.;
+    vec2 size = abs(a_bounds.zw - a_bounds.xy) / u_scale;
+
+    float f = fit_radii(size, a_radii_x, a_radii_y);
+
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+    v_color = a_color;
+    v_local = (a_pos - center) / u_scale;
+    v_half_size = size / 2.;
+    v_radii_x = a_radii_x * f;
+    v_radii_y = a_radii_y * f;
+  }
+"#;
+
+const ROUND_RECT_FS: &str = r#"
+  varying vec4 v_color;
+  varying vec2 v_local;
+  varying vec2 v_half_size;
+  varying vec4 v_radii_x;
+  varying vec4 v_radii_y;
+
+  void main() {
+    // pixel center is half a pixel from the edge
+    float coverage = clamp(0.5 - edge_distance(v_local, v_half_size, v_radii_x, v_radii_y), 0., 1.);
+
+    gl_FragColor = vec4(v_color.rgb, v_color.a * coverage) / 256.;
+  }
+"#;
+
+// a_pos is (0, 0) or (1, 1), the quad is as long as the side & as thick
+// as its width or the radii of its corners (whichever is bigger)
+const BORDER_VS: &str = r#"
+  uniform vec2 u_scale;
+
+  attribute vec2 a_pos;
+  attribute vec4 a_color;
+  attribute vec4 a_bounds;
+  attribute vec4 a_radii_x;
+  attribute vec4 a_radii_y;
+  // top, right, bottom, left
+  attribute vec4 a_widths;
+  // side, style
+  attribute vec4 a_side;
+
+  varying vec4 v_color;
+  varying vec2 v_local;
+  varying vec2 v_half_size;
+  varying vec4 v_radii_x;
+  varying vec4 v_radii_y;
+  varying vec4 v_widths;
+  // 1 for this side, 0 for the others
+  varying vec4 v_mask;
+  varying float v_style;
+
+  void main() {
+    vec2 lo = min(a_bounds.xy, a_bounds.zw);
+    vec2 hi = max(a_bounds.xy, a_bounds.zw);
+    vec2 size = (hi - lo) / u_scale;
+
+    float f = fit_radii(size, a_radii_x, a_radii_y);
+    vec4 rx = a_radii_x * f;
+    vec4 ry = a_radii_y * f;
+
+    v_mask = vec4(equal(vec4(a_side.x), vec4(0., 1., 2., 3.)));
+
+    vec4 bands = max(a_widths, vec4(max(ry.x, ry.y), max(rx.y, rx.z), max(ry.z, ry.w), max(rx.x, rx.w)));
+    vec2 rest = max(hi - lo - dot(bands, v_mask) * u_scale, 0.);
+
+    // shrink the rect from the opposite side
+    vec2 quad_lo = lo + vec2(v_mask.y, v_mask.x) * rest;
+    vec2 quad_hi = hi - vec2(v_mask.w, v_mask.z) * rest;
+    vec2 pos = mix(quad_lo, quad_hi, a_pos);
+
+    gl_Position = vec4(pos, 0.0, 1.0);
+    v_color = a_color;
+    v_local = (pos - (lo + hi) / 2.) / u_scale;
+    v_half_size = size / 2.;
+    v_radii_x = rx;
+    v_radii_y = ry;
+    v_widths = a_widths;
+    v_style = a_side.y;
+  }
+"#;
+
+// coverage is between the outer & the inner rounded rect, neighbouring quads
+// overlap in the corners so each pixel is drawn only by the side it belongs to
+const BORDER_FS: &str = r#"
+  varying vec4 v_color;
+  varying vec2 v_local;
+  varying vec2 v_half_size;
+  varying vec4 v_radii_x;
+  varying vec4 v_radii_y;
+  varying vec4 v_widths;
+  varying vec4 v_mask;
+  varying float v_style;
+
+  void main() {
+    vec2 p = v_local;
+    vec4 w = v_widths;
+
+    // distances from the outer edges relative to the widths, the smallest one wins
+    // (that's the diagonal in the corners), ties go to the first side
+    vec4 n = vec4(v_half_size.y - p.y, v_half_size.x - p.x, v_half_size.y + p.y, v_half_size.x + p.x) / max(w, 0.001);
+    n += vec4(0., 1., 2., 3.) * 0.0001;
+
+    vec4 others = n + v_mask * 10000.;
+
+    if (dot(n, v_mask) > min(min(others.x, others.y), min(others.z, others.w))) {
+      discard;
+    }
+
+    // inner radii are smaller by the widths of the sides
+    vec2 inner_center = vec2(w.w - w.y, w.z - w.x) / 2.;
+    vec2 inner_half_size = v_half_size - vec2(w.w + w.y, w.x + w.z) / 2.;
+    vec4 inner_rx = max(v_radii_x - w.wyyw, 0.);
+    vec4 inner_ry = max(v_radii_y - w.xxzz, 0.);
+
+    float outer = edge_distance(p, v_half_size, v_radii_x, v_radii_y);
+    float inner = edge_distance(p - inner_center, inner_half_size, inner_rx, inner_ry);
+    float coverage = clamp(0.5 - outer, 0., 1.) * clamp(0.5 + inner, 0., 1.);
+
+    float width = max(dot(w, v_mask), 0.001);
+    // along the side, from its center (so the pattern is symmetric)
+    float s = dot(p, vec2(v_mask.x + v_mask.z, v_mask.y + v_mask.w));
+
+    if (v_style > 2.5) {
+      // double, a third at the outer & a third at the inner edge
+      coverage *= min(clamp(width / 3. + 0.5 + outer, 0., 1.) + clamp(width / 3. + 0.5 - inner, 0., 1.), 1.);
+    } else if (v_style > 1.5) {
+      // dotted
+      float q = mod(s + width, 2. * width) - width;
+
+      coverage *= clamp(0.5 + width / 2. - length(vec2(q, outer + width / 2.)), 0., 1.);
+    } else if (v_style > 0.5) {
+      // dashed
+      float q = mod(s + 2.5 * width, 5. * width) - 2.5 * width;
+
+      coverage *= clamp(0.5 + 1.5 * width - abs(q), 0., 1.);
+    }
+
+    gl_FragColor = vec4(v_color.rgb, v_color.a * coverage) / 256.;
+  }
+"#;
+
+// a_pos is (0, 0) or (1, 1), the quad is the shadow + 3 standard deviations
+// (where it's almost zero) or just the box if it's inset
+const BOX_SHADOW_VS: &str = r#"
+  uniform vec2 u_scale;
+
+  attribute vec2 a_pos;
+  attribute vec4 a_color;
+  attribute vec4 a_bounds;
+  attribute vec4 a_radii_x;
+  attribute vec4 a_radii_y;
+  // offset, blur, spread
+  attribute vec4 a_shadow;
+  attribute vec4 a_inset;
+
+  varying vec4 v_color;
+  varying vec2 v_local;
+  varying vec2 v_half_size;
+  varying vec4 v_radii_x;
+  varying vec4 v_radii_y;
+  // offset, standard deviation, spread
+  varying vec4 v_shadow;
+  varying float v_inset;
+
+  void main() {
+    vec2 lo = min(a_bounds.xy, a_bounds.zw);
+    vec2 hi = max(a_bounds.xy, a_bounds.zw);
+    vec2 size = (hi - lo) / u_scale;
+
+    float f = fit_radii(size, a_radii_x, a_radii_y);
+
+    // too small blur is just antialiasing
+    float sigma = max(a_shadow.z / 2., 0.25);
+    vec2 margin = (a_shadow.w + 3. * sigma) * u_scale;
+    vec2 offset = a_shadow.xy * u_scale;
+    vec2 pos = a_inset.x > 0.5 ? mix(lo, hi, a_pos) : mix(lo + offset - margin, hi + offset + margin, a_pos);
+
+    gl_Position = vec4(pos, 0.0, 1.0);
+    v_color = a_color;
+    v_local = (pos - (lo + hi) / 2.) / u_scale;
+    v_half_size = size / 2.;
+    v_radii_x = a_radii_x * f;
+    v_radii_y = a_radii_y * f;
+    v_shadow = vec4(a_shadow.xy, sigma, a_shadow.w);
+    v_inset = a_inset.x;
+  }
+"#;
+
+// gaussian blur of a rounded rect is separable only without the corners so it's
+// integrated analytically (erf) along x & with a few samples along y
+// https://madebyevan.com/shaders/fast-rounded-rectangle-shadows/
+const BOX_SHADOW_FS: &str = r#"
+  varying vec4 v_color;
+  varying vec2 v_local;
+  varying vec2 v_half_size;
+  varying vec4 v_radii_x;
+  varying vec4 v_radii_y;
+  varying vec4 v_shadow;
+  varying float v_inset;
+
+  float gaussian(float x, float sigma) {
+    return exp(-(x * x) / (2. * sigma * sigma)) / (2.5066283 * sigma);
+  }
+
+  // approximation (max. error 5e-4)
+  vec2 erf(vec2 x) {
+    vec2 s = sign(x);
+    vec2 a = abs(x);
+
+    x = 1. + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+    x *= x;
+
+    return s - s / (x * x);
+  }
+
+  // where the row starts/ends (ellipses of the corners)
+  float row_extent(float y, vec2 half_size, vec2 r) {
+    float d = max(abs(y) - (half_size.y - r.y), 0.) / max(r.y, 0.001);
+
+    return half_size.x - r.x + r.x * sqrt(max(1. - d * d, 0.));
+  }
+
+  // blurred row, y is inside of the shape
+  float row(float x, float y, vec2 half_size, vec4 rx, vec4 ry, float sigma) {
+    float left = row_extent(y, half_size, y > 0. ? vec2(rx.x, ry.x) : vec2(rx.w, ry.w));
+    float right = row_extent(y, half_size, y > 0. ? vec2(rx.y, ry.y) : vec2(rx.z, ry.z));
+    vec2 integral = erf((x + vec2(left, -right)) * (0.7071068 / sigma));
+
+    return max(0.5 * (integral.x - integral.y), 0.);
+  }
+
+  float shadow(vec2 p, vec2 half_size, vec4 rx, vec4 ry, float sigma) {
+    if (half_size.x <= 0. || half_size.y <= 0.) {
+      return 0.;
+    }
+
+    // only the rows in 3 standard deviations (& inside of the shape) matter
+    float start = clamp(-3. * sigma, p.y - half_size.y, p.y + half_size.y);
+    float end = clamp(3. * sigma, p.y - half_size.y, p.y + half_size.y);
+    float dy = (end - start) / 4.;
+    float y = start + dy * 0.5;
+    float value = 0.;
+
+    for (int i = 0; i < 4; i++) {
+      value += row(p.x, p.y - y, half_size, rx, ry, sigma) * gaussian(y, sigma) * dy;
+      y += dy;
+    }
+
+    return value;
+  }
+
+  void main() {
+    float box = clamp(0.5 - edge_distance(v_local, v_half_size, v_radii_x, v_radii_y), 0., 1.);
+
+    // inset shadow is the inverse of a smaller shape
+    float spread = v_inset > 0.5 ? -v_shadow.w : v_shadow.w;
+    vec4 rx = max(v_radii_x + spread, 0.) * step(0.001, v_radii_x);
+    vec4 ry = max(v_radii_y + spread, 0.) * step(0.001, v_radii_y);
+    float value = shadow(v_local - v_shadow.xy, v_half_size + spread, rx, ry, v_shadow.z);
+
+    // like in CSS, it's never under the box (or outside of it if it's inset)
+    float coverage = v_inset > 0.5 ? box * (1. - value) : value * (1. - box);
+
+    gl_FragColor = vec4(v_color.rgb, v_color.a * coverage) / 256.;
+  }
+"#;
+
+const GRADIENT_VS: &str = r#"
+  #version 100
+
+  uniform vec2 u_scale;
+
+  attribute vec2 a_pos;
+  // kind, extend, dither
+  attribute vec4 a_gradient;
+  attribute vec4 a_bounds;
+  attribute vec4 a_ramp;
+  attribute vec4 a_geometry;
+
+  varying vec2 v_local;
+  varying vec4 v_gradient;
+  varying vec3 v_ramp;
+  varying vec4 v_geometry;
+
+  void main() {
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+    v_local = (a_pos - (a_bounds.xy + a_bounds.zw) / 2.) / u_scale;
+    v_gradient = a_gradient;
+    v_ramp = a_ramp.xyz;
+    v_geometry = a_geometry;
+  }
+"#;
+
+// t is the position on the ramp, extend modes are done here too
+// (so the texture can stay CLAMP_TO_EDGE)
+const GRADIENT_FS: &str = r#"
+  #version 100
+
+  #ifdef GL_FRAGMENT_PRECISION_HIGH
+    precision highp float;
+  #else
+    precision mediump float;
+  #endif
+
+  uniform sampler2D u_texture;
+
+  varying vec2 v_local;
+  varying vec4 v_gradient;
+  varying vec3 v_ramp;
+  varying vec4 v_geometry;
+
+  void main() {
+    vec2 p = v_local - v_geometry.xy;
+    float t;
+
+    if (v_gradient.x < 0.5) {
+      // linear, projected on the line from start to end
+      vec2 d = v_geometry.zw - v_geometry.xy;
+
+      t = dot(p, d) / max(dot(d, d), 0.0001);
+    } else if (v_gradient.x < 1.5) {
+      // radial
+      t = length(p / max(v_geometry.zw, 0.0001));
+    } else {
+      // conic, clockwise from the top (y goes up)
+      t = fract((atan(p.x, p.y) - v_geometry.z) / 6.2831853);
+    }
+
+    if (v_gradient.y < 0.5) {
+      t = clamp(t, 0., 1.);
+    } else if (v_gradient.y < 1.5) {
+      t = fract(t);
+    } else {
+      t = 1. - abs(mod(t, 2.) - 1.);
+    }
+
+    vec4 color = texture2D(u_texture, vec2(mix(v_ramp.x, v_ramp.y, t), v_ramp.z));
+
+    // interleaved gradient noise, +-half of the RGB565 step
+    if (v_gradient.z > 0.5) {
+      float noise = fract(52.9829189 * fract(dot(gl_FragCoord.xy, vec2(0.06711056, 0.00583715))));
+
+      color.rgb += (noise - 0.5) / 32.;
+    }
+
+    gl_FragColor = color;
+  }
+"#;
+
+// stencil has no antialiasing so it's either in or out
+const CLIP_MASK_FS: &str = r#"
+  varying vec4 v_color;
+  varying vec2 v_local;
+  varying vec2 v_half_size;
+  varying vec4 v_radii_x;
+  varying vec4 v_radii_y;
+
+  void main() {
+    if (edge_distance(v_local, v_half_size, v_radii_x, v_radii_y) > 0.) {
+      discard;
+    }
+
+    gl_FragColor = vec4(0.);
+  }
+"#;
+
+const IMAGE_VS: &str = r#"
+  #version 100
+
+  attribute vec2 a_pos;
+  attribute vec2 a_uv;
+
+  varying vec2 v_uv;
+
+  void main() {
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+    v_uv = a_uv;
+  }
+"#;
+
+const IMAGE_FS: &str = r#"
+  #version 100
+
+  precision mediump float;
+
+  uniform sampler2D u_texture;
+
+  varying vec2 v_uv;
+
+  void main() {
+    gl_FragColor = texture2D(u_texture, v_uv);
+  }
+"#;
+
+// glyphs are in pixels, relative to the text origin (u_pos) so that texts
+// can be moved/recolored without touching their buffers
+//
+// atlas has coverage in alpha
+const TEXT_VS: &str = r#"
+  #version 100
+
+  uniform vec2 u_pos;
+  uniform vec2 u_scale;
+
+  attribute vec2 a_pos;
+  attribute vec2 a_uv;
+
+  varying vec2 v_uv;
+
+  void main() {
+    gl_Position = vec4(u_pos + a_pos * u_scale, 0.0, 1.0);
+    v_uv = a_uv;
+  }
+"#;
+
+const TEXT_FS: &str = r#"
+  #version 100
+
+  precision mediump float;
+
+  uniform vec4 u_color;
+  uniform sampler2D u_texture;
+
+  varying vec2 v_uv;
+
+  void main() {
+    float coverage = texture2D(u_texture, v_uv).a;
+
+    gl_FragColor = vec4(u_color.rgb, u_color.a * coverage) / 256.;
+  }
+"#;
+
+// same vertices, atlas has distance in alpha (0.5 is the edge) and it's
+// sampled with LINEAR so the edge is sharp at any scale
+//
+// GLES 2 has no fwidth() without an extension so the edge width
+// is computed for the text size on the CPU
+const SDF_TEXT_FS: &str = r#"
+  #version 100
+
+  precision mediump float;
+
+  uniform vec4 u_color;
+  uniform float u_smoothing;
+  uniform sampler2D u_texture;
+
+  varying vec2 v_uv;
+
+  void main() {
+    float distance = texture2D(u_texture, v_uv).a;
+    float coverage = smoothstep(0.5 - u_smoothing, 0.5 + u_smoothing, distance);
+
+    gl_FragColor = vec4(u_color.rgb, u_color.a * coverage) / 256.;
+  }
+"#;
+
+// atlas has RGB coverage, alpha of the result is left untouched by both passes
+const LCD_TEXT_MASK_FS: &str = r#"
+  #version 100
+
+  precision mediump float;
+
+  uniform vec4 u_color;
+  uniform sampler2D u_texture;
+
+  varying vec2 v_uv;
+
+  void main() {
+    vec3 coverage = texture2D(u_texture, v_uv).rgb;
+
+    gl_FragColor = vec4(coverage * u_color.a / 256., 0.);
+  }
+"#;
+
+const LCD_TEXT_FS: &str = r#"
+  #version 100
+
+  precision mediump float;
+
+  uniform vec4 u_color;
+  uniform sampler2D u_texture;
+
+  varying vec2 v_uv;
+
+  void main() {
+    vec3 coverage = texture2D(u_texture, v_uv).rgb;
+
+    gl_FragColor = vec4(u_color.rgb / 256. * coverage * u_color.a / 256., 0.);
+  }
+"#;
+
+// atlas has the glyph's own RGBA, the text color is only for fading
+const COLOR_TEXT_FS: &str = r#"
+  #version 100
+
+  precision mediump float;
+
+  uniform vec4 u_color;
+  uniform sampler2D u_texture;
+
+  varying vec2 v_uv;
+
+  void main() {
+    vec4 color = texture2D(u_texture, v_uv);
+
+    gl_FragColor = vec4(color.rgb, color.a * u_color.a / 256.);
+  }
+"#;
+
+unsafe fn check() {
+    let err = gl::GetError();
+    if err != gl::NO_ERROR {
+        panic!("gl err {}", err);
+    }
+}
+
+// from gleam
+fn get_shader_info_log(shader: GLuint) -> String {
+    let mut max_len = [0];
+    unsafe {
+        get_shader_iv(shader, gl::INFO_LOG_LENGTH, &mut max_len);
+    }
+    if max_len[0] == 0 {
+        return String::new();
+    }
+    let mut result = vec![0u8; max_len[0] as usize];
+    let mut result_len = 0 as GLsizei;
+    unsafe {
+        gl::GetShaderInfoLog(
+            shader,
+            max_len[0] as GLsizei,
+            &mut result_len,
+            result.as_mut_ptr() as *mut GLchar,
+        );
+    }
+    result.truncate(if result_len > 0 {
+        result_len as usize
+    } else {
+        0
+    });
+    String::from_utf8(result).unwrap()
+}
+unsafe fn get_shader_iv(shader: GLuint, pname: GLenum, result: &mut [GLint]) {
+    assert!(!result.is_empty());
+    gl::GetShaderiv(shader, pname, result.as_mut_ptr());
+}
+fn get_program_info_log(program: GLuint) -> String {
+    let mut max_len = [0];
+    unsafe {
+        get_program_iv(program, gl::INFO_LOG_LENGTH, &mut max_len);
+    }
+    if max_len[0] == 0 {
+        return String::new();
+    }
+    let mut result = vec![0u8; max_len[0] as usize];
+    let mut result_len = 0 as GLsizei;
+    unsafe {
+        gl::GetProgramInfoLog(
+            program,
+            max_len[0] as GLsizei,
+            &mut result_len,
+            result.as_mut_ptr() as *mut GLchar,
+        );
+    }
+    result.truncate(if result_len > 0 {
+        result_len as usize
+    } else {
+        0
+    });
+    String::from_utf8(result).unwrap()
+}
+unsafe fn get_program_iv(program: GLuint, pname: GLenum, result: &mut [GLint]) {
+    assert!(!result.is_empty());
+    gl::GetProgramiv(program, pname, result.as_mut_ptr());
+}
+
+unsafe fn shader_program(vertex_shader_source: &str, fragment_shader_source: &str) -> u32 {
+    let vertex_shader = shader(gl::VERTEX_SHADER, vertex_shader_source);
+    let fragment_shader = shader(gl::FRAGMENT_SHADER, fragment_shader_source);
+
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vertex_shader);
+    gl::AttachShader(program, fragment_shader);
+
+    for (location, names) in ATTRIBUTES.iter().enumerate() {
+        for name in *names {
+            gl::BindAttribLocation(program, location as GLuint, c_str(name).as_ptr());
+        }
+    }
+
+    gl::LinkProgram(program);
+
+    let mut success = gl::FALSE as GLint;
+
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+
+    if success != gl::TRUE as GLint {
+        panic!("{}", get_program_info_log(program));
+    }
+
+    gl::DeleteShader(vertex_shader);
+    gl::DeleteShader(fragment_shader);
+
+    program
+}
+
+unsafe fn uniform_location(program: GLuint, name: &str) -> GLint {
+    gl::GetUniformLocation(program, c_str(name).as_ptr())
+}
+
+fn c_str(s: &str) -> CString {
+    CString::new(s.as_bytes()).expect("get CString")
+}
+
+unsafe fn shader(shader_type: u32, source: &str) -> u32 {
+    let shader = gl::CreateShader(shader_type);
+
+    gl::ShaderSource(
+        shader,
+        1,
+        &(CString::new(source.as_bytes()).expect("get CString")).as_ptr(),
+        ptr::null(),
+    );
+    gl::CompileShader(shader);
+
+    let mut success = gl::FALSE as GLint;
+
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+
+    if success != gl::TRUE as GLint {
+        panic!("{}", get_shader_info_log(shader));
+    }
+
+    shader
+}
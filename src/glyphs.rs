@@ -0,0 +1,515 @@
+// glyphs are rasterized just once (per font & size) into their own atlas
+// and then every text is just a bunch of textured quads
+//
+// in the sdf mode, glyphs are rasterized once for all sizes (at SDF_SIZE)
+// and the atlas has (signed) distances to the edge instead of coverage
+// so it can be scaled without getting blurry
+//
+// coverage & lcd glyphs are positioned with 1/SUBPIXEL_STEPS precision, so
+// each glyph can be there up to SUBPIXEL_STEPS times (shifted a bit)
+//
+// lcd glyphs have separate coverage for each of the RGB subpixels, each one
+// is simply rasterized with the glyph shifted by a third of pixel, which is
+// the same as 3x horizontal resolution & a box filter over 3 subpixels
+//
+// color glyphs (emoji) are in the same atlas, with their own RGBA & no
+// subpixel positioning (nor sdf/lcd), they are drawn without the text color
+//
+// glyphs are counted by the texts which use them (their uvs are in the text
+// buffers), unused ones are kept for a while (texts are often re-created with
+// the same glyphs) & then evicted, the least recently released first
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::atlas::{Atlas, AtlasAlloc};
+use super::backend::{Backend, TextureFilter};
+use super::font::FontMetrics;
+
+pub type GlyphId = u16;
+
+/// Handle to a font added to the renderer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FontId(pub(crate) usize);
+
+/// Coverage bitmap, one byte per pixel, top row first
+#[derive(Clone, Debug, Default)]
+pub struct GlyphBitmap {
+    pub width: usize,
+    pub height: usize,
+    /// from the pen position to the left edge, in pixels
+    pub left: i32,
+    /// from the baseline up to the top edge, in pixels
+    pub top: i32,
+    pub coverage: Vec<u8>,
+    /// RGBA (not premultiplied) of color glyphs, they are not tinted
+    pub color: Option<Vec<u8>>,
+}
+
+/// Something which can turn glyph ids into bitmaps (a font, typically)
+pub trait GlyphRasterizer {
+    /// size is in pixels (em size), offset is a horizontal shift in pixels
+    /// (for subpixel positioning, it can be ignored if that's not possible)
+    fn rasterize(&self, glyph: GlyphId, size: f32, offset: f32) -> GlyphBitmap;
+
+    /// font for `PositionedGlyph::font` (index - 1), if there's a fallback chain
+    fn fallback(&self, index: usize) -> Option<&dyn GlyphRasterizer> {
+        None
+    }
+
+    /// for backgrounds & lines of text spans
+    fn metrics(&self, size: f32) -> FontMetrics {
+        FontMetrics::approximate(size)
+    }
+}
+
+/// Glyph & its pen position in pixels, relative to the text origin
+/// (which is on the baseline, y goes up)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionedGlyph {
+    pub id: GlyphId,
+    pub x: f32,
+    pub y: f32,
+    /// in the fallback chain, 0 is the font itself
+    pub font: usize,
+}
+
+/// Every glyph is a filled box, useful until there's a real font
+pub struct BoxGlyphs;
+
+impl GlyphRasterizer for BoxGlyphs {
+    fn rasterize(&self, glyph: GlyphId, size: f32, offset: f32) -> GlyphBitmap {
+        let width = (size * 0.5).round() as usize;
+        let height = (size * 0.7).round() as usize;
+
+        GlyphBitmap {
+            width,
+            height,
+            left: 0,
+            top: height as i32,
+            coverage: vec![255; width * height],
+            color: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlyphMode {
+    Coverage,
+    Sdf,
+    /// RGB coverage, for horizontal RGB subpixel screens
+    Lcd,
+}
+
+/// Where the glyph is in the atlas & how to place it
+/// (in atlas pixels, see `GlyphCache::scale()`)
+#[derive(Clone, Copy, Debug)]
+pub struct CachedGlyph {
+    pub alloc: AtlasAlloc,
+    pub left: i32,
+    pub top: i32,
+    /// RGBA glyph, not to be tinted
+    pub color: bool,
+    /// for `GlyphCache::release()`
+    pub(crate) key: GlyphKey,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct GlyphKey {
+    font: FontId,
+    fallback: usize,
+    glyph: GlyphId,
+    size: u32,
+    subpixel: u8,
+}
+
+pub struct GlyphCache {
+    pub atlas: Atlas,
+    mode: GlyphMode,
+    glyphs: HashMap<GlyphKey, Entry>,
+    // color glyphs are the same for all subpixels, -> the one with subpixel 0
+    aliases: HashMap<GlyphKey, GlyphKey>,
+    // glyphs which are not used by any text, by the time they were released
+    unused: BTreeMap<u64, GlyphKey>,
+    time: u64,
+    solid: Option<AtlasAlloc>,
+}
+
+struct Entry {
+    // None for empty glyphs (space)
+    glyph: Option<CachedGlyph>,
+    // how many times it's used (in all texts)
+    refs: usize,
+    // key in `unused` (if refs is 0)
+    released: u64,
+}
+
+// so that NEAREST doesn't pick up anything from the neighbours
+const PADDING: usize = 1;
+
+// quarter pixels are good enough (& 4x as many glyphs already)
+const SUBPIXEL_STEPS: f32 = 4.;
+
+// how many unused glyphs are kept before they are evicted
+const MAX_UNUSED: usize = 256;
+
+// sdf glyphs are rasterized at this size (em, in pixels)
+const SDF_SIZE: f32 = 48.;
+
+// how far (in pixels of SDF_SIZE) is the distance field going, it's also
+// the padding because the field continues outside of the glyph
+const SDF_SPREAD: usize = 6;
+
+impl GlyphCache {
+    pub fn new(page_size: usize, mode: GlyphMode) -> Self {
+        // sdf has to be interpolated
+        let filter = match mode {
+            GlyphMode::Coverage | GlyphMode::Lcd => TextureFilter::Nearest,
+            GlyphMode::Sdf => TextureFilter::Linear,
+        };
+
+        Self {
+            atlas: Atlas::new(page_size, filter),
+            mode,
+            glyphs: HashMap::new(),
+            aliases: HashMap::new(),
+            unused: BTreeMap::new(),
+            time: 0,
+            solid: None,
+        }
+    }
+
+    /// pen x -> (x where to put the glyph, subpixel for `get()`)
+    pub fn subpixel(&self, x: f32) -> (f32, u8) {
+        match self.mode {
+            GlyphMode::Coverage | GlyphMode::Lcd => {
+                let steps = (x * SUBPIXEL_STEPS).round();
+                let whole = (steps / SUBPIXEL_STEPS).floor();
+
+                (whole, (steps - whole * SUBPIXEL_STEPS) as u8)
+            }
+            GlyphMode::Sdf => (x, 0),
+        }
+    }
+
+    /// `fallback` is `PositionedGlyph::font` (rasterizer is already the right one),
+    /// the glyph is kept in the atlas until it's released (as many times as it was returned)
+    pub fn get(&mut self, font: FontId, fallback: usize, rasterizer: &dyn GlyphRasterizer, glyph: GlyphId, size: f32, subpixel: u8) -> Option<CachedGlyph> {
+        let size = match self.mode {
+            GlyphMode::Coverage | GlyphMode::Lcd => size,
+            GlyphMode::Sdf => SDF_SIZE,
+        };
+        let key = GlyphKey { font, fallback, glyph, size: size.to_bits(), subpixel };
+        let key = self.aliases.get(&key).copied().unwrap_or(key);
+
+        if self.glyphs.contains_key(&key) {
+            return self.acquire(key);
+        }
+
+        let offset = subpixel as f32 / SUBPIXEL_STEPS;
+        let bitmap = match self.mode {
+            GlyphMode::Coverage | GlyphMode::Lcd => rasterizer.rasterize(glyph, size, offset),
+            GlyphMode::Sdf => rasterizer.rasterize(glyph, size, 0.),
+        };
+
+        // color glyphs are not shifted, so all the subpixels share one
+        if bitmap.color.is_some() && subpixel != 0 {
+            self.aliases.insert(key, GlyphKey { subpixel: 0, ..key });
+
+            return self.get(font, fallback, rasterizer, glyph, size, 0);
+        }
+
+        let cached = match (self.mode, &bitmap.color) {
+            (_, Some(color)) => self.add(key, &bitmap, PADDING, color_pixels(&bitmap, color, PADDING), true),
+            (GlyphMode::Coverage, None) => self.add(key, &bitmap, PADDING, coverage_pixels(&bitmap, PADDING), false),
+            (GlyphMode::Sdf, None) => self.add(key, &bitmap, SDF_SPREAD, sdf_pixels(&bitmap, SDF_SPREAD), false),
+            (GlyphMode::Lcd, None) => {
+                // R is on the left, so the glyph goes right, see the top
+                let r = rasterizer.rasterize(glyph, size, offset + 1. / 3.);
+                let b = rasterizer.rasterize(glyph, size, offset - 1. / 3.);
+                let (bitmap, pixels) = lcd_pixels([&r, &bitmap, &b], PADDING);
+
+                self.add(key, &bitmap, PADDING, pixels, false)
+            }
+        };
+
+        // empty ones are never released, they are just forgotten with time
+        self.glyphs.insert(key, Entry { glyph: cached, refs: 1, released: 0 });
+
+        if cached.is_none() {
+            self.release(key);
+        }
+
+        cached
+    }
+
+    /// for every `get()` which returned a glyph, when it's not used anymore
+    pub(crate) fn release(&mut self, key: GlyphKey) {
+        let e = self.glyphs.get_mut(&key).expect("released glyph");
+
+        e.refs -= 1;
+
+        if e.refs > 0 {
+            return;
+        }
+
+        self.time += 1;
+        e.released = self.time;
+        self.unused.insert(self.time, key);
+
+        while self.unused.len() > MAX_UNUSED {
+            let (_, key) = self.unused.pop_first().unwrap();
+
+            if let Some(g) = self.glyphs.remove(&key).and_then(|e| e.glyph) {
+                self.atlas.deallocate(g.alloc);
+            }
+
+            self.aliases.retain(|_, base| *base != key);
+        }
+    }
+
+    fn acquire(&mut self, key: GlyphKey) -> Option<CachedGlyph> {
+        let e = self.glyphs.get_mut(&key).unwrap();
+
+        if e.refs == 0 {
+            self.unused.remove(&e.released);
+        }
+
+        e.refs += 1;
+
+        let cached = e.glyph;
+
+        // so it's the most recent one again
+        if cached.is_none() {
+            self.release(key);
+        }
+
+        cached
+    }
+
+    /// opaque white block for backgrounds & lines (in any mode), quads should
+    /// use the uv of its center so that nothing else is ever sampled
+    pub fn solid(&mut self) -> AtlasAlloc {
+        if let Some(alloc) = self.solid {
+            return alloc;
+        }
+
+        let alloc = self.atlas.allocate(3, 3);
+
+        self.atlas.write(alloc, vec![255; 4 * 3 * 3]);
+        self.solid = Some(alloc);
+
+        alloc
+    }
+
+    /// atlas pixels -> pixels of the given text size
+    pub fn scale(&self, size: f32) -> f32 {
+        match self.mode {
+            GlyphMode::Coverage | GlyphMode::Lcd => 1.,
+            GlyphMode::Sdf => size / SDF_SIZE,
+        }
+    }
+
+    /// `Uniform::Smoothing` for the given text size, so that the edge is
+    /// antialiased over ~1 screen pixel
+    pub fn smoothing(&self, size: f32) -> f32 {
+        // distance goes from 0 to 1 over 2 * SDF_SPREAD atlas pixels
+        0.5 / (2. * SDF_SPREAD as f32 * self.scale(size))
+    }
+
+    // pixels are RGBA, with padding around the bitmap
+    fn add(&mut self, key: GlyphKey, bitmap: &GlyphBitmap, padding: usize, pixels: Vec<u8>, color: bool) -> Option<CachedGlyph> {
+        if bitmap.width == 0 || bitmap.height == 0 {
+            return None;
+        }
+
+        let width = bitmap.width + 2 * padding;
+        let height = bitmap.height + 2 * padding;
+        let alloc = self.atlas.allocate(width, height);
+
+        self.atlas.write(alloc, pixels);
+
+        Some(CachedGlyph {
+            alloc,
+            left: bitmap.left - padding as i32,
+            top: bitmap.top + padding as i32,
+            color,
+            key,
+        })
+    }
+
+    pub fn upload(&mut self, backend: &mut impl Backend) {
+        self.atlas.upload(backend);
+    }
+}
+
+// atlas is RGBA, coverage goes to alpha
+fn coverage_pixels(bitmap: &GlyphBitmap, padding: usize) -> Vec<u8> {
+    let width = bitmap.width + 2 * padding;
+    let mut pixels = vec![0; 4 * width * (bitmap.height + 2 * padding)];
+
+    for y in 0..bitmap.height {
+        for x in 0..bitmap.width {
+            let i = 4 * ((y + padding) * width + x + padding);
+
+            pixels[i..i + 4].copy_from_slice(&[255, 255, 255, bitmap.coverage[y * bitmap.width + x]]);
+        }
+    }
+
+    pixels
+}
+
+fn color_pixels(bitmap: &GlyphBitmap, color: &[u8], padding: usize) -> Vec<u8> {
+    let width = bitmap.width + 2 * padding;
+    let mut pixels = vec![0; 4 * width * (bitmap.height + 2 * padding)];
+
+    for y in 0..bitmap.height {
+        let i = 4 * ((y + padding) * width + padding);
+
+        pixels[i..i + 4 * bitmap.width].copy_from_slice(&color[4 * y * bitmap.width..4 * (y + 1) * bitmap.width]);
+    }
+
+    pixels
+}
+
+// RGB coverage from 3 bitmaps (which can be of different size),
+// the returned bitmap is just where it goes (coverage is in the pixels)
+fn lcd_pixels(channels: [&GlyphBitmap; 3], padding: usize) -> (GlyphBitmap, Vec<u8>) {
+    let non_empty = || channels.iter().filter(|b| b.width > 0 && b.height > 0);
+
+    let left = non_empty().map(|b| b.left).min().unwrap_or(0);
+    let top = non_empty().map(|b| b.top).max().unwrap_or(0);
+    let right = non_empty().map(|b| b.left + b.width as i32).max().unwrap_or(0);
+    let bottom = non_empty().map(|b| b.top - b.height as i32).min().unwrap_or(0);
+
+    let bitmap = GlyphBitmap {
+        width: (right - left) as usize,
+        height: (top - bottom) as usize,
+        left,
+        top,
+        coverage: Vec::new(),
+        color: None,
+    };
+
+    let width = bitmap.width + 2 * padding;
+    let mut pixels = vec![0; 4 * width * (bitmap.height + 2 * padding)];
+
+    for (c, b) in channels.iter().enumerate() {
+        let (dx, dy) = ((b.left - left) as usize, (top - b.top) as usize);
+
+        for y in 0..b.height {
+            for x in 0..b.width {
+                let i = 4 * ((y + dy + padding) * width + x + dx + padding);
+
+                pixels[i + c] = b.coverage[y * b.width + x];
+                pixels[i + 3] = pixels[i + 3].max(pixels[i + c]);
+            }
+        }
+    }
+
+    (bitmap, pixels)
+}
+
+// distance to the nearest pixel on the other side of the edge, brute-force
+// but it's done just once per glyph & the search is limited to the spread
+//
+// edge pixels use their coverage instead, so the edge is not stair-stepped
+fn sdf_pixels(bitmap: &GlyphBitmap, spread: usize) -> Vec<u8> {
+    let width = bitmap.width + 2 * spread;
+    let height = bitmap.height + 2 * spread;
+
+    let coverage = |x: isize, y: isize| {
+        let (x, y) = (x - spread as isize, y - spread as isize);
+
+        if x < 0 || y < 0 || x >= bitmap.width as isize || y >= bitmap.height as isize {
+            return 0.;
+        }
+
+        bitmap.coverage[y as usize * bitmap.width + x as usize] as f32 / 255.
+    };
+
+    let r = spread as isize;
+    let mut pixels = vec![0; 4 * width * height];
+
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let c = coverage(x, y);
+            let inside = c >= 0.5;
+
+            let distance = if c > 0. && c < 1. {
+                c - 0.5
+            } else {
+                let mut nearest = spread as f32 + 0.5;
+
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        if (coverage(x + dx, y + dy) >= 0.5) != inside {
+                            nearest = nearest.min(((dx * dx + dy * dy) as f32).sqrt());
+                        }
+                    }
+                }
+
+                if inside { nearest - 0.5 } else { 0.5 - nearest }
+            };
+
+            // -spread..spread -> 0..1
+            let value = 0.5 + distance / (2. * spread as f32);
+            let i = 4 * (y as usize * width + x as usize);
+
+            pixels[i..i + 4].copy_from_slice(&[255, 255, 255, (value.clamp(0., 1.) * 255.).round() as u8]);
+        }
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(cache: &mut GlyphCache, glyph: GlyphId, size: f32) -> CachedGlyph {
+        cache.get(FontId(0), 0, &BoxGlyphs, glyph, size, 0).unwrap()
+    }
+
+    #[test]
+    fn eviction() {
+        let mut cache = GlyphCache::new(1024, GlyphMode::Coverage);
+        let used = get(&mut cache, 0, 10.);
+
+        for i in 0..2 * MAX_UNUSED {
+            let g = get(&mut cache, i as GlyphId + 1, 10.);
+
+            cache.release(g.key);
+        }
+
+        // only the unused ones are evicted
+        assert_eq!(cache.atlas.stats().pages[0].allocations, MAX_UNUSED + 1);
+        assert!(cache.glyphs.contains_key(&used.key));
+        assert!(!cache.glyphs.contains_key(&GlyphKey { glyph: 1, ..used.key }));
+
+        // released ones can be used again (until they are evicted)
+        let last = get(&mut cache, 2 * MAX_UNUSED as GlyphId, 10.);
+
+        cache.release(last.key);
+
+        assert_eq!(get(&mut cache, 2 * MAX_UNUSED as GlyphId, 10.).alloc, last.alloc);
+        assert_eq!(cache.atlas.stats().pages[0].allocations, MAX_UNUSED + 1);
+    }
+
+    #[test]
+    fn shared() {
+        let mut cache = GlyphCache::new(1024, GlyphMode::Coverage);
+        let a = get(&mut cache, 0, 10.);
+        let b = get(&mut cache, 0, 10.);
+
+        cache.release(a.key);
+
+        for i in 0..2 * MAX_UNUSED {
+            let g = get(&mut cache, i as GlyphId + 1, 10.);
+
+            cache.release(g.key);
+        }
+
+        // still used by b
+        assert_eq!(cache.glyphs[&b.key].refs, 1);
+    }
+}
@@ -0,0 +1,87 @@
+// gradients with any number of stops
+//
+// stops are baked into a ramp (a row of texels) in an atlas so that
+// all gradients can be drawn with the same program, the shader just finds
+// the position on the ramp (t) for the pixel & samples it with LINEAR
+//
+// hard stops are a bit soft then (1/RAMP_SIZE of the gradient length)
+
+use super::{Pos, RGBA};
+
+/// Where the stops go, all points are in pixels relative to the center of
+/// the rect (y goes up)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientKind {
+    /// from start (offset 0) to end (offset 1)
+    Linear { start: Pos, end: Pos },
+    /// from the center (offset 0) to the ellipse with these radii (offset 1)
+    Radial { center: Pos, radii: Pos },
+    /// around the center, clockwise from the top, rotated by the angle (in radians)
+    Conic { center: Pos, angle: f32 },
+}
+
+/// What's before/after the first/last stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extend {
+    /// the color of the first/last stop
+    Pad,
+    Repeat,
+    /// repeated but every second one is reversed
+    Reflect,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    /// (offset, color), offsets should be sorted (from 0 to 1)
+    pub stops: Vec<(f32, RGBA)>,
+    pub extend: Extend,
+    /// add some noise so there are no bands on 16-bit (RGB565) displays
+    pub dither: bool,
+}
+
+impl Gradient {
+    pub fn new(kind: GradientKind, stops: Vec<(f32, RGBA)>) -> Self {
+        Self { kind, stops, extend: Extend::Pad, dither: false }
+    }
+}
+
+// texels in one ramp
+pub(crate) const RAMP_SIZE: usize = 256;
+
+// RGBA8 of RAMP_SIZE texels, interpolated with premultiplied alpha
+// (so that transparent stops don't make it darker) like in CSS
+pub(crate) fn ramp(stops: &[(f32, RGBA)]) -> Vec<u8> {
+    let premultiplied = |c: RGBA| {
+        let a = c.3 as f32 / 255.;
+
+        [c.0 as f32 * a, c.1 as f32 * a, c.2 as f32 * a, c.3 as f32]
+    };
+
+    let mut pixels = Vec::with_capacity(4 * RAMP_SIZE);
+
+    for i in 0..RAMP_SIZE {
+        let t = i as f32 / (RAMP_SIZE - 1) as f32;
+
+        // last stop before t & first one after it
+        let next = stops.iter().position(|s| s.0 > t).unwrap_or(stops.len());
+        let color = match (next.checked_sub(1).map(|i| stops[i]), stops.get(next)) {
+            (Some((o0, c0)), Some(&(o1, c1))) => {
+                let (c0, c1) = (premultiplied(c0), premultiplied(c1));
+                let f = (t - o0) / (o1 - o0);
+
+                [0, 1, 2, 3].map(|i| c0[i] + (c1[i] - c0[i]) * f)
+            }
+            (Some((_, c)), None) | (None, Some(&(_, c))) => premultiplied(c),
+            (None, None) => [0.; 4],
+        };
+
+        // back to straight alpha (that's what we blend with)
+        let a = color[3] / 255.;
+        let straight = |c: f32| if a > 0. { (c / a).round().min(255.) as u8 } else { 0 };
+
+        pixels.extend_from_slice(&[straight(color[0]), straight(color[1]), straight(color[2]), color[3].round() as u8]);
+    }
+
+    pixels
+}
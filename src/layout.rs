@@ -0,0 +1,379 @@
+// paragraphs, text -> lines -> positioned glyphs
+//
+// the whole text is shaped once to know the widths, lines are then found
+// greedily at the unicode line break opportunities (UAX #14) & every line
+// is shaped again on its own (ligatures/kerning can't cross the break)
+// but with the direction of its paragraph
+//
+// it works with anything which can shape (vector & bitmap fonts)
+
+use std::ops::Range;
+
+use unicode_bidi::{BidiInfo, Level};
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+
+use super::font::{Font, FontMetrics};
+use super::glyphs::PositionedGlyph;
+use super::shaping::ShapedText;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+    /// stretch spaces so that the line fills the whole width
+    /// (except the last line & lines ending with a newline)
+    Justify,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayoutOptions {
+    /// None means no wrapping, only newlines break
+    pub max_width: Option<f32>,
+    pub align: Align,
+    /// multiplier of the font's line height
+    pub line_height: f32,
+    /// lines after this are dropped
+    pub max_lines: Option<usize>,
+    /// put "…" at the end of the last line if something was dropped
+    pub ellipsis: bool,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            align: Align::Left,
+            line_height: 1.,
+            max_lines: None,
+            ellipsis: false,
+        }
+    }
+}
+
+/// Laid out text, the origin is the top-left corner
+/// (so all the baselines are below, y goes up)
+#[derive(Clone, Debug, Default)]
+pub struct Paragraph {
+    /// what `create_text()` needs
+    pub glyphs: Vec<PositionedGlyph>,
+    pub lines: Vec<Line>,
+    /// max_width or the widest line
+    pub width: f32,
+    pub height: f32,
+    /// some lines were dropped because of max_lines
+    pub truncated: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    /// bytes of the text on this line (without the trailing whitespace)
+    pub text: Range<usize>,
+    /// in `Paragraph::glyphs`
+    pub glyphs: Range<usize>,
+    /// start of the line (after alignment)
+    pub x: f32,
+    pub baseline: f32,
+    pub width: f32,
+}
+
+// what the layout needs from a font
+pub(crate) trait Shaper {
+    fn shape_with_level(&self, text: &str, size: f32, level: Option<Level>) -> ShapedText;
+
+    fn metrics(&self, size: f32) -> FontMetrics;
+
+    fn has_char(&self, ch: char) -> bool;
+}
+
+impl Shaper for Font {
+    fn shape_with_level(&self, text: &str, size: f32, level: Option<Level>) -> ShapedText {
+        Font::shape_with_level(self, text, size, level)
+    }
+
+    fn metrics(&self, size: f32) -> FontMetrics {
+        Font::metrics(self, size)
+    }
+
+    fn has_char(&self, ch: char) -> bool {
+        self.glyph_index(ch).is_some()
+    }
+}
+
+impl Font {
+    pub fn layout(&self, text: &str, size: f32, options: &LayoutOptions) -> Paragraph {
+        layout(self, text, size, options)
+    }
+}
+
+pub(crate) fn layout(font: &impl Shaper, text: &str, size: f32, options: &LayoutOptions) -> Paragraph {
+    let widths = ClusterWidths::new(&font.shape_with_level(text, size, None));
+    let mut lines = break_lines(text, &widths, options.max_width);
+
+    let truncated = matches!(options.max_lines, Some(max) if lines.len() > max);
+
+    if let Some(max) = options.max_lines {
+        lines.truncate(max);
+    }
+
+    let metrics = font.metrics(size);
+    let line_height = metrics.line_height() * options.line_height;
+    // extra space is split above & below, like in CSS
+    let half_leading = (line_height - metrics.line_height()) / 2.;
+
+    let bidi = BidiInfo::new(text, None);
+    let mut shaped_lines = Vec::new();
+
+    for (i, &(ref range, hard)) in lines.iter().enumerate() {
+        let last = i == lines.len() - 1;
+        let level = bidi.paragraphs.iter().find(|p| p.range.contains(&range.start)).map(|p| p.level);
+
+        if last && truncated && options.ellipsis {
+            let (range, shaped) = ellipsize(font, text, range.clone(), size, level, &widths, options.max_width);
+
+            shaped_lines.push((range, shaped, false));
+        } else {
+            let justify = !hard && !last && options.max_width.is_some();
+
+            shaped_lines.push((range.clone(), font.shape_with_level(&text[range.clone()], size, level), justify));
+        }
+    }
+
+    let width = options
+        .max_width
+        .unwrap_or_else(|| shaped_lines.iter().map(|(_, s, _)| s.width).fold(0., f32::max));
+
+    let mut paragraph = Paragraph {
+        glyphs: Vec::new(),
+        lines: Vec::new(),
+        width,
+        height: line_height * lines.len() as f32,
+        truncated,
+    };
+
+    for (i, (range, shaped, justify)) in shaped_lines.into_iter().enumerate() {
+        let baseline = -(half_leading + metrics.ascent + i as f32 * line_height);
+        let line_text = &text[range.clone()];
+
+        let (x, spacing) = match options.align {
+            Align::Left => (0., 0.),
+            Align::Center => ((width - shaped.width) / 2., 0.),
+            Align::Right => (width - shaped.width, 0.),
+            Align::Justify if justify => {
+                let spaces = shaped.glyphs.iter().filter(|g| is_space(line_text, g.cluster)).count();
+
+                (0., if spaces > 0 { (width - shaped.width) / spaces as f32 } else { 0. })
+            }
+            Align::Justify => (0., 0.),
+        };
+
+        let start = paragraph.glyphs.len();
+        let mut extra = 0.;
+
+        for g in &shaped.glyphs {
+            paragraph.glyphs.push(PositionedGlyph { id: g.id, x: x + extra + g.x, y: baseline + g.y, font: g.font });
+
+            if spacing > 0. && is_space(line_text, g.cluster) {
+                extra += spacing;
+            }
+        }
+
+        paragraph.lines.push(Line {
+            text: range,
+            glyphs: start..paragraph.glyphs.len(),
+            x,
+            baseline,
+            width: shaped.width + extra,
+        });
+    }
+
+    paragraph
+}
+
+// cut the line so that the "…" fits too
+fn ellipsize(font: &impl Shaper, text: &str, range: Range<usize>, size: f32, level: Option<Level>, widths: &ClusterWidths, max_width: Option<f32>) -> (Range<usize>, ShapedText) {
+    let ellipsis = if font.has_char('…') { "…" } else { "..." };
+    let ellipsis_width = font.shape_with_level(ellipsis, size, None).width;
+
+    let mut end = range.end;
+
+    if let Some(max_width) = max_width {
+        while end > range.start && widths.width(range.start..end) + ellipsis_width > max_width {
+            end = widths.prev_boundary(range.start, end);
+            end = range.start + text[range.start..end].trim_end().len();
+        }
+    }
+
+    let range = range.start..end;
+
+    (range.clone(), font.shape_with_level(&format!("{}{}", &text[range], ellipsis), size, level))
+}
+
+// (line, ends with a mandatory break)
+fn break_lines(text: &str, widths: &ClusterWidths, max_width: Option<f32>) -> Vec<(Range<usize>, bool)> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut last_break = None;
+
+    // negative (or NaN) is one cluster per line, empty lines always fit
+    let max_width = max_width.map(|max| max.max(0.));
+
+    // trailing whitespace doesn't count
+    let trimmed = |start: usize, end: usize| start..start + text[start..end].trim_end().len();
+    let fits = |range: Range<usize>| max_width.is_none_or(|max| widths.width(range) <= max);
+
+    for (pos, opportunity) in linebreaks(text) {
+        let mut wrapped = false;
+
+        while !fits(trimmed(start, pos)) {
+            let end = match last_break.take() {
+                Some(end) if end > start => end,
+                // no opportunity, break the word wherever it overflows
+                _ => widths.fit(start, pos, max_width.unwrap_or(0.)),
+            };
+            let line = trimmed(start, end);
+
+            // just the whitespace after a broken word
+            if !line.is_empty() {
+                lines.push((line, false));
+            }
+
+            start = end;
+            wrapped = true;
+        }
+
+        if opportunity == BreakOpportunity::Mandatory {
+            // wrapped right before the break, so that line ends there
+            match lines.last_mut() {
+                Some(last) if wrapped && start == pos => last.1 = true,
+                _ => lines.push((trimmed(start, pos), true)),
+            }
+
+            start = pos;
+            last_break = None;
+        } else {
+            last_break = Some(pos);
+        }
+    }
+
+    lines
+}
+
+fn is_space(text: &str, cluster: usize) -> bool {
+    text[cluster..].chars().next().is_some_and(char::is_whitespace)
+}
+
+// advance of every cluster (in logical order) so that any part of the text
+// can be measured without shaping it again
+struct ClusterWidths {
+    clusters: Vec<usize>,
+    // prefix sums, one more than clusters
+    offsets: Vec<f32>,
+}
+
+impl ClusterWidths {
+    fn new(shaped: &ShapedText) -> Self {
+        let mut advances: Vec<(usize, f32)> = shaped.glyphs.iter().map(|g| (g.cluster, g.advance)).collect();
+
+        advances.sort_by_key(|(cluster, _)| *cluster);
+
+        let mut clusters = Vec::new();
+        let mut offsets = vec![0.];
+
+        for (cluster, advance) in advances {
+            if clusters.last() == Some(&cluster) {
+                *offsets.last_mut().unwrap() += advance;
+            } else {
+                clusters.push(cluster);
+                offsets.push(offsets.last().unwrap() + advance);
+            }
+        }
+
+        Self { clusters, offsets }
+    }
+
+    fn width(&self, range: Range<usize>) -> f32 {
+        self.offsets[self.index(range.end)] - self.offsets[self.index(range.start)]
+    }
+
+    // last cluster boundary so that start..end fits (but at least one cluster)
+    fn fit(&self, start: usize, end: usize, max_width: f32) -> usize {
+        let first = self.index(start);
+        let mut i = first + 1;
+
+        while i < self.clusters.len() && self.clusters[i] < end && self.offsets[i + 1] - self.offsets[first] <= max_width {
+            i += 1;
+        }
+
+        self.clusters.get(i).copied().unwrap_or(end).min(end)
+    }
+
+    fn prev_boundary(&self, start: usize, end: usize) -> usize {
+        let i = self.index(end);
+
+        if i == 0 {
+            return start;
+        }
+
+        self.clusters[i - 1].max(start)
+    }
+
+    // first cluster at or after pos
+    fn index(&self, pos: usize) -> usize {
+        self.clusters.partition_point(|c| *c < pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap_font::tests::font;
+
+    fn lines(text: &str, options: &LayoutOptions) -> Vec<Range<usize>> {
+        // 8px per char
+        layout(&font("abc. "), text, 10., options).lines.into_iter().map(|l| l.text).collect()
+    }
+
+    #[test]
+    fn wrapping() {
+        let options = LayoutOptions { max_width: Some(40.), ..Default::default() };
+
+        assert_eq!(lines("aa bb cc", &options), [0..5, 6..8]);
+        assert_eq!(lines("aa\nbb cc", &options), [0..2, 3..8]);
+        assert_eq!(lines("aa bb cc", &Default::default()), [Range { start: 0, end: 8 }]);
+
+        let p = layout(&font("abc. "), "aa bb cc", 10., &options);
+
+        assert_eq!((p.width, p.height), (40., 20.));
+        assert_eq!(p.lines[1], Line { text: 6..8, glyphs: 5..7, x: 0., baseline: -18., width: 16. });
+        assert_eq!(p.glyphs[5].x, 0.);
+        assert_eq!(p.glyphs[5].y, -18.);
+    }
+
+    #[test]
+    fn word_break() {
+        let options = LayoutOptions { max_width: Some(24.), ..Default::default() };
+
+        assert_eq!(lines("aaaaaaa bb", &options), [0..3, 3..6, 6..7, 8..10]);
+    }
+
+    #[test]
+    fn no_width() {
+        // one cluster per line (& no endless loop)
+        for max_width in [0., -10., f32::NAN] {
+            let options = LayoutOptions { max_width: Some(max_width), ..Default::default() };
+
+            assert_eq!(lines("ab c", &options), [0..1, 1..2, 3..4]);
+        }
+    }
+
+    #[test]
+    fn ellipsis() {
+        let options = LayoutOptions { max_width: Some(40.), max_lines: Some(1), ellipsis: true, ..Default::default() };
+        let p = layout(&font("abc. "), "aa bb cc", 10., &options);
+
+        // there's no "…" in the font
+        assert!(p.truncated);
+        assert_eq!(p.lines, [Line { text: 0..2, glyphs: 0..5, x: 0., baseline: -8., width: 40. }]);
+    }
+}
@@ -1,38 +1,250 @@
 #![allow(unused_variables, dead_code)]
 
-use std::ffi::CString;
-
 use std::mem;
-use std::ptr;
-use std::str;
-
-use gl::types::*;
+use std::ops::Range;
+
+use atlas::{Atlas, AtlasAlloc};
+use gradient::RAMP_SIZE;
+use glyphs::{GlyphCache, GlyphKey, GlyphMode};
+
+mod atlas;
+mod backend;
+mod bitmap_font;
+mod color_glyphs;
+mod font;
+mod gl_backend;
+mod glyphs;
+mod gradient;
+mod layout;
+mod raster;
+mod shaping;
+
+pub use atlas::{AtlasPageStats, AtlasStats};
+pub use bitmap_font::BitmapFont;
+pub use backend::{Backend, BufferId, BufferKind, Call, Program, RecordingBackend, Stencil, TextureFilter, TextureId, Uniform};
+pub use font::{Font, FontError, FontMetrics};
+pub use gl_backend::GlBackend;
+pub use gradient::{Extend, Gradient, GradientKind};
+pub use glyphs::{BoxGlyphs, FontId, GlyphBitmap, GlyphId, GlyphRasterizer, PositionedGlyph};
+pub use layout::{Align, LayoutOptions, Line, Paragraph};
+pub use raster::{Framebuffer, SoftwareBackend};
+pub use shaping::{ShapedGlyph, ShapedText, TextMetrics};
 
 /// Application unit (or something similar, unit of measure)
 /// TODO(later): Integer type could save some CPU & memory
 type Au = f32;
 
 /// 2D Point
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Pos(pub Au, pub Au);
 
 /// Colors are RGBA, we could save 4x8 bits for each opaque quad but
 /// it's probably not worth the additional complexity
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct RGBA(pub u8, pub u8, pub u8, pub u8);
 
-pub struct NotSureWhat {
-    rect_program: u32,
-    image_program: u32,
-    text_program: u32,
+/// Corner radii in pixels, each one is (horizontal, vertical) so the corners
+/// can be elliptical, radii which don't fit are scaled down (like in CSS)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Radii {
+    pub top_left: Pos,
+    pub top_right: Pos,
+    pub bottom_right: Pos,
+    pub bottom_left: Pos,
+}
+
+impl Radii {
+    /// the same circular radius for all corners
+    pub fn all(radius: Au) -> Self {
+        let r = Pos(radius, radius);
+
+        Self { top_left: r, top_right: r, bottom_right: r, bottom_left: r }
+    }
+}
+
+/// How a side of a border is drawn (like in CSS), dashes are 3x longer
+/// than the width, dots are round & gaps between them are as big as the dots
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorderStyle {
+    Solid,
+    Dashed,
+    Dotted,
+    /// two lines, each one third of the width
+    Double,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BorderSide {
+    /// in pixels
+    pub width: Au,
+    pub color: RGBA,
+    pub style: BorderStyle,
+}
+
+impl BorderSide {
+    pub fn solid(width: Au, color: RGBA) -> Self {
+        Self { width, color, style: BorderStyle::Solid }
+    }
+}
+
+/// Sides are inside of the bounds, corners are split between the sides on
+/// the diagonal from the outer to the inner corner, inner radii are the outer
+/// ones minus the widths of the sides
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Border {
+    pub top: BorderSide,
+    pub right: BorderSide,
+    pub bottom: BorderSide,
+    pub left: BorderSide,
+    pub radii: Radii,
+}
+
+impl Border {
+    /// the same for all sides
+    pub fn new(side: BorderSide, radii: Radii) -> Self {
+        Self { top: side, right: side, bottom: side, left: side, radii }
+    }
+}
+
+/// Blurred shadow of a (rounded) box, like the CSS box-shadow
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoxShadow {
+    /// in pixels, y goes up
+    pub offset: Pos,
+    /// like in CSS, the standard deviation is half of it
+    pub blur: Au,
+    /// makes the shadow bigger (or smaller if negative) on all sides
+    pub spread: Au,
+    pub color: RGBA,
+    /// of the box, radii of the shadow grow/shrink with the spread
+    pub radii: Radii,
+    /// inside of the box (pressed buttons), otherwise it's only outside of it
+    pub inset: bool,
+}
+
+impl BoxShadow {
+    pub fn new(offset: Pos, blur: Au, color: RGBA) -> Self {
+        Self { offset, blur, spread: 0., color, radii: Radii::all(0.), inset: false }
+    }
+}
+
+/// Only what's inside is drawn (until the matching `PopClip`), nested clips
+/// are intersected, rounded ones are not antialiased
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clip {
+    pub a: Pos,
+    pub b: Pos,
+    pub radii: Radii,
+}
+
+impl Clip {
+    /// axis-aligned, it's just a scissor
+    pub fn rect(a: Pos, b: Pos) -> Self {
+        Self { a, b, radii: Radii::all(0.) }
+    }
+
+    /// needs stencil (8 bits are enough for 255 nested ones)
+    pub fn round(a: Pos, b: Pos, radii: Radii) -> Self {
+        Self { a, b, radii }
+    }
+
+    fn is_rounded(&self) -> bool {
+        let Radii { top_left, top_right, bottom_right, bottom_left } = self.radii;
+
+        [top_left, top_right, bottom_right, bottom_left].iter().any(|r| r.0 > 0. && r.1 > 0.)
+    }
+}
+
+/// RGBA8 pixels, rows are `stride` bytes apart (first row is the top one)
+#[derive(Clone, Copy, Debug)]
+pub struct ImageData<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub pixels: &'a [u8],
+}
+
+/// Part of a rich text with its own style, spans follow each other on the
+/// baseline (so it's one line, paragraphs need a rich text for each line)
+#[derive(Clone, Copy, Debug)]
+pub struct TextSpan<'a> {
+    pub font: FontId,
+    pub size: f32,
+    /// relative to the start of the span
+    pub glyphs: &'a [PositionedGlyph],
+    /// where the next span starts (typically `ShapedText::width`)
+    pub width: f32,
+    pub color: RGBA,
+    /// from descent to ascent of the font, over the whole width
+    pub background: Option<RGBA>,
+    pub underline: bool,
+    pub strikethrough: bool,
+}
+
+impl <'a> TextSpan<'a> {
+    pub fn new(font: FontId, size: f32, glyphs: &'a [PositionedGlyph], width: f32, color: RGBA) -> Self {
+        Self { font, size, glyphs, width, color, background: None, underline: false, strikethrough: false }
+    }
+}
+
+impl <'a> ImageData<'a> {
+    // GLES 2 can't unpack with stride (there's no UNPACK_ROW_LENGTH)
+    fn packed(&self) -> Vec<u8> {
+        let row = 4 * self.width;
+
+        if self.stride == row {
+            return self.pixels[..row * self.height].to_vec();
+        }
+
+        let mut packed = Vec::with_capacity(row * self.height);
+
+        for y in 0..self.height {
+            packed.extend_from_slice(&self.pixels[y * self.stride..y * self.stride + row]);
+        }
+
+        packed
+    }
+}
+
+pub struct NotSureWhat<B: Backend = GlBackend> {
+    backend: B,
 
     rect_buffer: Buffer<Quad<RGBA>>,
+    round_rect_buffer: Buffer<Quad<RoundRect>>,
+    border_buffer: Buffer<Quad<BorderEdge>>,
+    borders: SlotMap<[QuadId; 4]>,
+    box_shadow_buffer: Buffer<Quad<Shadow>>,
+    gradient_buffer: Buffer<Quad<GradientData>>,
+    gradients: SlotMap<GradientRamp>,
+    // ramps of all gradients
+    gradient_atlas: Atlas,
+    // uvs
     image_buffer: Buffer<Quad<Pos>>,
-    texts: LeakyVec<Text>,
+    images: SlotMap<Image>,
+    atlas: Atlas,
+    texts: SlotMap<Text>,
+    fonts: Vec<(Box<dyn GlyphRasterizer>, GlyphMode)>,
+    glyph_cache: GlyphCache,
+    sdf_glyph_cache: GlyphCache,
+    lcd_glyph_cache: GlyphCache,
+    // masks of rounded clips, rebuilt with the display list
+    clip_buffer: Buffer<Quad<RoundRect>>,
 
     batches: Vec<Batch>,
     // shared for all batches to save bandwidth
-    index_buffer: Buffer<VertexIndex>,
+    index_buffer: IndexBuffer,
+}
+
+impl NotSureWhat {
+    pub fn new() -> Self {
+        Self::with_backend(GlBackend::new())
+    }
+}
+
+impl Default for NotSureWhat {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// stateful, low-level renderer
@@ -47,263 +259,770 @@ pub struct NotSureWhat {
 /// - add/remove (to render anything)
 /// - bounds/position and/or dimension changes (if something is pushing items when expanded, etc.) 
 /// - change color (hover)
-impl NotSureWhat {
-    pub fn new() -> Self {
-        unsafe {
-            // not used but webgl & opengl core profile require it
-            let mut vao = 0;
-            gl::GenVertexArrays(1, &mut vao);
-            gl::BindVertexArray(vao);
+impl <B: Backend> NotSureWhat<B> {
+    pub fn with_backend(backend: B) -> Self {
+        Self {
+            backend,
+
+            rect_buffer: Buffer::new(),
+            round_rect_buffer: Buffer::new(),
+            border_buffer: Buffer::new(),
+            borders: SlotMap::new(),
+            box_shadow_buffer: Buffer::new(),
+            gradient_buffer: Buffer::new(),
+            gradients: SlotMap::new(),
+            gradient_atlas: Atlas::new(ATLAS_PAGE_SIZE, TextureFilter::Linear),
+            image_buffer: Buffer::new(),
+            images: SlotMap::new(),
+            atlas: Atlas::new(ATLAS_PAGE_SIZE, TextureFilter::Nearest),
+            texts: SlotMap::new(),
+            fonts: Vec::new(),
+            glyph_cache: GlyphCache::new(ATLAS_PAGE_SIZE, GlyphMode::Coverage),
+            sdf_glyph_cache: GlyphCache::new(ATLAS_PAGE_SIZE, GlyphMode::Sdf),
+            lcd_glyph_cache: GlyphCache::new(ATLAS_PAGE_SIZE, GlyphMode::Lcd),
+            clip_buffer: Buffer::new(),
+
+            batches: Vec::new(),
+            index_buffer: IndexBuffer::new(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn create_rect(&mut self, a: Pos, b: Pos, color: RGBA) -> RectId {
+        RectId(self.rect_buffer.add_quad(a, b, color))
+    }
 
-            check();
+    pub fn set_rect_bounds(&mut self, id: RectId, a: Pos, b: Pos) {
+        self.rect_buffer.set_quad_bounds(id.0, a, b);
+    }
 
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::BlendEquation(gl::FUNC_ADD);
+    pub fn set_rect_color(&mut self, id: RectId, color: RGBA) {
+        self.rect_buffer.set_quad_data(id.0, color);
+    }
 
-            check();
+    pub fn remove_rect(&mut self, id: RectId) {
+        self.rect_buffer.remove(id.0);
+    }
 
-            Self {
-                rect_program: shader_program(RECT_VS, RECT_FS),
-                image_program: shader_program(IMAGE_VS, IMAGE_FS),
-                text_program: shader_program(TEXT_VS, TEXT_FS),
+    // like create_rect() but the corners are antialiased in the fragment shader
+    // (so the quad is the same & it can be batched with other round rects)
+    pub fn create_round_rect(&mut self, a: Pos, b: Pos, radii: Radii, color: RGBA) -> RoundRectId {
+        RoundRectId(self.round_rect_buffer.add_quad(a, b, RoundRect::new(a, b, radii, color)))
+    }
 
-                rect_buffer: Buffer::new(),
-                image_buffer: Buffer::new(),
-                texts: LeakyVec::new(),
+    pub fn set_round_rect_bounds(&mut self, id: RoundRectId, a: Pos, b: Pos) {
+        let mut data = self.round_rect_buffer.data[id.0].0[0].1;
 
-                batches: Vec::new(),
-                index_buffer: Buffer::new(),
-            }
+        // shader needs the whole rect in every vertex
+        data.bounds = [a.0, a.1, b.0, b.1];
+
+        self.round_rect_buffer.set_quad_bounds(id.0, a, b);
+        self.round_rect_buffer.set_quad_data(id.0, data);
+    }
+
+    pub fn set_round_rect_radii(&mut self, id: RoundRectId, radii: Radii) {
+        let mut data = self.round_rect_buffer.data[id.0].0[0].1;
+
+        data.set_radii(radii);
+
+        self.round_rect_buffer.set_quad_data(id.0, data);
+    }
+
+    pub fn set_round_rect_color(&mut self, id: RoundRectId, color: RGBA) {
+        let mut data = self.round_rect_buffer.data[id.0].0[0].1;
+
+        data.color = color;
+
+        self.round_rect_buffer.set_quad_data(id.0, data);
+    }
+
+    pub fn remove_round_rect(&mut self, id: RoundRectId) {
+        self.round_rect_buffer.remove(id.0);
+    }
+
+    // quad for each side (so the inside is not drawn at all), they are
+    // placed in the vertex shader because their sizes are in pixels
+    pub fn create_border(&mut self, a: Pos, b: Pos, border: &Border) -> BorderId {
+        let quads = BorderEdge::new(a, b, border).map(|edge| self.border_buffer.add_quad(Pos(0., 0.), Pos(1., 1.), edge));
+
+        BorderId(self.borders.add(quads))
+    }
+
+    pub fn set_border_bounds(&mut self, id: BorderId, a: Pos, b: Pos) {
+        for quad in self.borders[id.0] {
+            let mut edge = self.border_buffer.data[quad].0[0].1;
+
+            edge.bounds = [a.0, a.1, b.0, b.1];
+
+            self.border_buffer.set_quad_data(quad, edge);
         }
+    }
 
+    pub fn set_border(&mut self, id: BorderId, border: &Border) {
+        let quads = self.borders[id.0];
+        let bounds = self.border_buffer.data[quads[0]].0[0].1.bounds;
+        let edges = BorderEdge::new(Pos(bounds[0], bounds[1]), Pos(bounds[2], bounds[3]), border);
+
+        for (quad, edge) in quads.iter().zip(edges) {
+            self.border_buffer.set_quad_data(*quad, edge);
+        }
     }
 
-    pub fn create_rect(&mut self, a: Pos, b: Pos, color: RGBA) -> RectId {
-        self.rect_buffer.add_quad(a, b, color)
+    /// of all the sides
+    pub fn set_border_color(&mut self, id: BorderId, color: RGBA) {
+        for quad in self.borders[id.0] {
+            let mut edge = self.border_buffer.data[quad].0[0].1;
+
+            edge.color = color;
+
+            self.border_buffer.set_quad_data(quad, edge);
+        }
     }
 
-    pub fn set_rect_bounds(&mut self, id: RectId, a: Pos, b: Pos) {
-        self.rect_buffer.set_quad_bounds(id, a, b);
+    pub fn remove_border(&mut self, id: BorderId) {
+        for quad in self.borders[id.0] {
+            self.border_buffer.remove(quad);
+        }
+
+        self.borders.remove(id.0);
     }
 
-    pub fn set_rect_color(&mut self, id: RectId, color: RGBA) {
-        // self.rect_buffer[id].
+    // a, b are the bounds of the box, the quad is made bigger (for the blur)
+    // in the vertex shader, the shadow is evaluated in the fragment shader
+    // so there are no offscreen passes
+    pub fn create_box_shadow(&mut self, a: Pos, b: Pos, shadow: &BoxShadow) -> BoxShadowId {
+        BoxShadowId(self.box_shadow_buffer.add_quad(Pos(0., 0.), Pos(1., 1.), Shadow::new(a, b, shadow)))
     }
 
-    pub fn remove_rect(&mut self, id: RectId) {
-        self.rect_buffer.remove(id);
+    pub fn set_box_shadow_bounds(&mut self, id: BoxShadowId, a: Pos, b: Pos) {
+        let mut data = self.box_shadow_buffer.data[id.0].0[0].1;
+
+        data.bounds = [a.0, a.1, b.0, b.1];
+
+        self.box_shadow_buffer.set_quad_data(id.0, data);
+    }
+
+    pub fn set_box_shadow(&mut self, id: BoxShadowId, shadow: &BoxShadow) {
+        let bounds = self.box_shadow_buffer.data[id.0].0[0].1.bounds;
+
+        self.box_shadow_buffer.set_quad_data(id.0, Shadow::new(Pos(bounds[0], bounds[1]), Pos(bounds[2], bounds[3]), shadow));
+    }
+
+    pub fn set_box_shadow_color(&mut self, id: BoxShadowId, color: RGBA) {
+        let mut data = self.box_shadow_buffer.data[id.0].0[0].1;
+
+        data.color = color;
+
+        self.box_shadow_buffer.set_quad_data(id.0, data);
+    }
+
+    pub fn remove_box_shadow(&mut self, id: BoxShadowId) {
+        self.box_shadow_buffer.remove(id.0);
+    }
+
+    // stops are in the gradient atlas so that all gradients
+    // (from the same atlas page) can be drawn together
+    pub fn create_gradient(&mut self, a: Pos, b: Pos, gradient: &Gradient) -> GradientId {
+        let ramp = self.gradient_atlas.allocate(RAMP_SIZE, 1);
+
+        self.gradient_atlas.write(ramp, gradient::ramp(&gradient.stops));
+
+        let data = GradientData::new(a, b, gradient, ramp_uv(&self.gradient_atlas, ramp));
+
+        GradientId(self.gradients.add(GradientRamp {
+            quad: self.gradient_buffer.add_quad(a, b, data),
+            ramp,
+        }))
+    }
+
+    pub fn set_gradient_bounds(&mut self, id: GradientId, a: Pos, b: Pos) {
+        let quad = self.gradients[id.0].quad;
+        let mut data = self.gradient_buffer.data[quad].0[0].1;
+
+        data.bounds = [a.0, a.1, b.0, b.1];
+
+        self.gradient_buffer.set_quad_bounds(quad, a, b);
+        self.gradient_buffer.set_quad_data(quad, data);
+    }
+
+    // ramps are all of the same size so it's written to the same place
+    pub fn set_gradient(&mut self, id: GradientId, gradient: &Gradient) {
+        let GradientRamp { quad, ramp } = self.gradients[id.0];
+        let bounds = self.gradient_buffer.data[quad].0[0].1.bounds;
+
+        self.gradient_atlas.write(ramp, gradient::ramp(&gradient.stops));
+
+        let data = GradientData::new(Pos(bounds[0], bounds[1]), Pos(bounds[2], bounds[3]), gradient, ramp_uv(&self.gradient_atlas, ramp));
+
+        self.gradient_buffer.set_quad_data(quad, data);
+    }
+
+    pub fn remove_gradient(&mut self, id: GradientId) {
+        let gradient = self.gradients[id.0];
+
+        self.gradient_atlas.deallocate(gradient.ramp);
+        self.gradient_buffer.remove(gradient.quad);
+        self.gradients.remove(id.0);
     }
 
-    // TODO: image texture
-    pub fn create_image(&mut self, a: Pos, b: Pos) -> ImageId {
-        // TODO
-        0
+    // a is bottom-left, b is top-right so that the image is not upside down,
+    // it can't be empty (0x0)
+    pub fn create_image(&mut self, a: Pos, b: Pos, data: ImageData) -> ImageId {
+        let alloc = self.atlas.allocate(data.width, data.height);
+        let (uv_a, uv_b) = quad_uv(&self.atlas, alloc);
+
+        self.atlas.write(alloc, data.packed());
+
+        ImageId(self.images.add(Image {
+            quad: self.image_buffer.add_quad_uv(a, b, uv_a, uv_b),
+            alloc,
+        }))
     }
 
+    pub fn set_image_bounds(&mut self, id: ImageId, a: Pos, b: Pos) {
+        self.image_buffer.set_quad_bounds(self.images[id.0].quad, a, b);
+    }
+
+    // keeps the size
     pub fn set_image_pos(&mut self, id: ImageId, pos: Pos) {
-        //self.batches[id.0].uniforms[0] = Uniform::Float2(pos.0, pos.1);
+        let quad = self.images[id.0].quad;
+        let v = &self.image_buffer.data[quad].0;
+        let (a, b) = (v[0].0, v[3].0);
+
+        self.image_buffer.set_quad_bounds(quad, pos, Pos(pos.0 + b.0 - a.0, pos.1 + b.1 - a.1));
+    }
+
+    // can be of different size, it will be scaled to the current bounds
+    pub fn set_image_data(&mut self, id: ImageId, data: ImageData) {
+        let Image { quad, mut alloc } = self.images[id.0];
+
+        if (alloc.width, alloc.height) != (data.width, data.height) {
+            self.atlas.deallocate(alloc);
+
+            alloc = self.atlas.allocate(data.width, data.height);
+            self.images[id.0].alloc = alloc;
+
+            let (uv_a, uv_b) = quad_uv(&self.atlas, alloc);
+
+            self.image_buffer.set_quad_uv(quad, uv_a, uv_b);
+        }
+
+        self.atlas.write(alloc, data.packed());
     }
 
     pub fn remove_image(&mut self, id: ImageId) {
-        // TODO
+        let image = self.images[id.0];
+
+        self.atlas.deallocate(image.alloc);
+        self.image_buffer.remove(image.quad);
+        self.images.remove(id.0);
     }
 
-    // TODO: glyphs: &[GlyphType]
-    pub fn create_text(&mut self, pos: Pos, glyphs: usize, color: RGBA) -> TextId {
-        let mut buffer = Buffer::new();
-        let mut x = 0.;
+    /// for images, glyphs & gradients, only the atlas pages created from now on are affected
+    pub fn set_atlas_page_size(&mut self, page_size: usize) {
+        self.atlas.set_page_size(page_size);
+        self.glyph_cache.atlas.set_page_size(page_size);
+        self.sdf_glyph_cache.atlas.set_page_size(page_size);
+        self.lcd_glyph_cache.atlas.set_page_size(page_size);
+        self.gradient_atlas.set_page_size(page_size);
+    }
+
+    pub fn atlas_stats(&self) -> AtlasStats {
+        self.atlas.stats()
+    }
+
+    pub fn glyph_atlas_stats(&self) -> AtlasStats {
+        self.glyph_cache.atlas.stats()
+    }
+
+    pub fn add_font(&mut self, font: impl GlyphRasterizer + 'static) -> FontId {
+        self.fonts.push((Box::new(font), GlyphMode::Coverage));
+
+        FontId(self.fonts.len() - 1)
+    }
+
+    /// texts of this font are rendered with distance fields so they stay sharp
+    /// at any size (one atlas entry for all sizes) but small sizes look a bit
+    /// worse than with `add_font()`, the same font can be added both ways
+    pub fn add_sdf_font(&mut self, font: impl GlyphRasterizer + 'static) -> FontId {
+        self.fonts.push((Box::new(font), GlyphMode::Sdf));
+
+        FontId(self.fonts.len() - 1)
+    }
+
+    /// texts of this font are antialiased for each RGB subpixel (sharper on
+    /// low-dpi screens), it needs horizontal RGB screen & opaque background
+    pub fn add_lcd_font(&mut self, font: impl GlyphRasterizer + 'static) -> FontId {
+        self.fonts.push((Box::new(font), GlyphMode::Lcd));
+
+        FontId(self.fonts.len() - 1)
+    }
+
+    // pos is the origin (on the baseline), glyphs are in pixels relative to that
+    pub fn create_text(&mut self, pos: Pos, font: FontId, size: f32, glyphs: &[PositionedGlyph], color: RGBA) -> TextId {
+        self.create_rich_text(pos, &[TextSpan::new(font, size, glyphs, 0., color)])
+    }
+
+    // one primitive (& one buffer) for all the spans, their colors are uniforms
+    // so they can be changed separately, see `set_span_color()`
+    pub fn create_rich_text(&mut self, pos: Pos, spans: &[TextSpan]) -> TextId {
+        // (kind, span, atlas page, a, b, uv_a, uv_b)
+        let mut quads = Vec::new();
+        let mut styles = Vec::new();
+        let mut glyphs = Vec::new();
+        let mut pen = 0.;
+
+        for (i, span) in spans.iter().enumerate() {
+            let (rasterizer, mode) = &self.fonts[span.font.0];
+            let cache = match mode {
+                GlyphMode::Coverage => &mut self.glyph_cache,
+                GlyphMode::Sdf => &mut self.sdf_glyph_cache,
+                GlyphMode::Lcd => &mut self.lcd_glyph_cache,
+            };
+            let scale = cache.scale(span.size);
+            let metrics = rasterizer.metrics(span.size);
+
+            // backgrounds & lines sample just the center of the solid block
+            let solid = cache.solid();
+            let (uv_a, uv_b) = quad_uv(&cache.atlas, solid);
+            let center = Pos((uv_a.0 + uv_b.0) / 2., (uv_a.1 + uv_b.1) / 2.);
+
+            if span.background.is_some() {
+                quads.push((RunKind::Background, i, solid.page, Pos(pen, metrics.descent), Pos(pen + span.width, metrics.ascent), center, center));
+            }
+
+            for g in span.glyphs {
+                let rasterizer = match g.font {
+                    0 => &**rasterizer,
+                    i => match rasterizer.fallback(i - 1) {
+                        Some(r) => r,
+                        None => continue,
+                    },
+                };
+
+                // whole pixels so that nothing is resampled (the fraction is in the glyph),
+                // sdf is resampled anyway, the span is part of it as its width isn't whole
+                let (x, subpixel) = cache.subpixel(pen + g.x);
+                let y = if *mode == GlyphMode::Sdf { g.y } else { g.y.round() };
+
+                let c = match cache.get(span.font, g.font, rasterizer, g.id, span.size, subpixel) {
+                    Some(c) => c,
+                    None => continue,
+                };
+
+                glyphs.push((*mode, c.key));
+
+                // color glyphs have no subpixel variants
+                let x = if c.color && *mode != GlyphMode::Sdf { (pen + g.x).round() } else { x };
+                let left = x + c.left as f32 * scale;
+                let top = y + c.top as f32 * scale;
+                let (uv_a, uv_b) = quad_uv(&cache.atlas, c.alloc);
+                let kind = if c.color { RunKind::ColorGlyphs } else { RunKind::Glyphs };
 
-        let glyph_width = 0.05;
-        let glyph_height = 0.1;
-        let advance = 0.01;
+                quads.push((kind, i, c.alloc.page, Pos(left, top - c.alloc.height as f32 * scale), Pos(left + c.alloc.width as f32 * scale, top), uv_a, uv_b));
+            }
+
+            // whole pixels & at least one, so they are sharp
+            let mut line = |position: f32, thickness: f32| {
+                let thickness = thickness.round().max(1.);
+                let top = position.round();
+
+                quads.push((RunKind::Lines, i, solid.page, Pos(pen, top - thickness), Pos(pen + span.width, top), center, center));
+            };
+
+            if span.underline {
+                line(metrics.underline_position, metrics.underline_thickness);
+            }
 
-        // TODO & should be somewhere else
-        for _ in 0..glyphs {
-            // for now we are rendering just colored quads
-            buffer.add_quad(Pos(x, 0.), Pos(x + glyph_width, glyph_height), color);
+            if span.strikethrough {
+                line(metrics.strikethrough_position, metrics.strikethrough_thickness);
+            }
 
-            //let glyph_uv = Pos(0., 0.);
-            //buffer.add_quad(Pos(x, 0.), Pos(x + 9., 16.), glyph_uv);
+            styles.push(SpanStyle {
+                color: span.color,
+                background: span.background.unwrap_or(RGBA(0, 0, 0, 0)),
+                mode: *mode,
+                smoothing: cache.smoothing(span.size),
+            });
 
-            x += glyph_width + advance;
+            pen += span.width;
         }
 
-        self.texts.add(Text {
-            pos, color, buffer
-        })
+        // backgrounds go first & lines last, then it's by span & glyphs from
+        // the same atlas page are drawn together
+        quads.sort_by_key(|&(kind, span, page, ..)| (kind, span, page));
+
+        let mut buffer = Buffer::new();
+        let mut runs: Vec<TextRun> = Vec::new();
+
+        for (kind, span, atlas_page, a, b, uv_a, uv_b) in quads {
+            buffer.add_quad_uv(a, b, uv_a, uv_b);
+
+            match runs.last_mut() {
+                Some(r) if (r.kind, r.span, r.atlas_page) == (kind, span, atlas_page) => r.quads += 1,
+                _ => runs.push(TextRun { kind, span, atlas_page, quads: 1 }),
+            }
+        }
+
+        TextId(self.texts.add(Text { pos, spans: styles, buffer, runs, glyphs }))
     }
 
+    // both are uniforms so there's nothing to upload
     pub fn set_text_pos(&mut self, id: TextId, pos: Pos) {
-        //self.batches[id.0].uniforms[0] = Uniform::Float2(pos.0, pos.1);
+        self.texts[id.0].pos = pos;
     }
 
+    /// of all the spans
     pub fn set_text_color(&mut self, id: TextId, color: RGBA) {
-        //self.batches[id.0].uniforms[1] = Uniform::Float3(color.0 as f32, color.1 as f32, color.2 as f32);
+        for span in &mut self.texts[id.0].spans {
+            span.color = color;
+        }
+    }
+
+    /// index in the spans given to `create_rich_text()`
+    pub fn set_span_color(&mut self, id: TextId, span: usize, color: RGBA) {
+        self.texts[id.0].spans[span].color = color;
+    }
+
+    /// only for spans which were created with some background
+    pub fn set_span_background(&mut self, id: TextId, span: usize, color: RGBA) {
+        self.texts[id.0].spans[span].background = color;
     }
 
     pub fn remove_text(&mut self, id: TextId) {
-        self.texts.remove(id);
+        let text = &mut self.texts[id.0];
+
+        // slot is going to be reused but the buffers would not
+        for page in text.buffer.pages.drain(..) {
+            self.backend.delete_buffer(page.id);
+        }
+
+        text.buffer = Buffer::new();
+
+        // so they can be evicted
+        for (mode, key) in text.glyphs.drain(..) {
+            match mode {
+                GlyphMode::Coverage => self.glyph_cache.release(key),
+                GlyphMode::Sdf => self.sdf_glyph_cache.release(key),
+                GlyphMode::Lcd => self.lcd_glyph_cache.release(key),
+            }
+        }
+
+        self.texts.remove(id.0);
     }
 
-    // TODO: skip up-to-date buffers
-    unsafe fn upload_buffers(&self) {
-        self.rect_buffer.upload();
+    // only what has changed since the last time
+    fn upload_buffers(&mut self) {
+        self.rect_buffer.upload(&mut self.backend);
+        self.round_rect_buffer.upload(&mut self.backend);
+        self.border_buffer.upload(&mut self.backend);
+        self.box_shadow_buffer.upload(&mut self.backend);
+        self.gradient_buffer.upload(&mut self.backend);
 
-        for t in &self.texts.data {
-            t.buffer.upload();
+        for t in &mut self.texts.data {
+            t.buffer.upload(&mut self.backend);
         }
+
+        self.image_buffer.upload(&mut self.backend);
+        self.clip_buffer.upload(&mut self.backend);
+        self.atlas.upload(&mut self.backend);
+        self.gradient_atlas.upload(&mut self.backend);
+        self.glyph_cache.upload(&mut self.backend);
+        self.sdf_glyph_cache.upload(&mut self.backend);
+        self.lcd_glyph_cache.upload(&mut self.backend);
+
+        self.index_buffer.upload(&mut self.backend);
     }
 
     // if there were changes in the rendering order
     pub fn set_display_list(&mut self, items: &[DisplayItem]) {
-        println!("list {:?}", &items);
-
         let mut batches = Vec::new();
         let mut indices = Vec::new();
 
-        // TODO: fusion
+        // for each PushClip, the last one is in effect
+        let mut clips: Vec<ClipEntry> = Vec::new();
+        let mut clip_state = (None, Stencil::Disabled);
+
+        self.clip_buffer.clear();
+
+        // consecutive items are fused into one batch whenever possible
+        // (same pipeline & same vertex buffer) so that 10k rects are just one draw call
         for it in items {
             match it {
-                DisplayItem::Rect(rect_id) => {
-                    let base = 4 * (*rect_id as VertexIndex);
+                DisplayItem::Rect(RectId(id)) => {
+                    assert!(self.rect_buffer.data.contains(*id), "stale {:?}", id);
 
-                    indices.push(base + 1);
-                    indices.push(base);
-                    indices.push(base + 3);
+                    let (page, quad) = page_of(id.index as usize);
 
-                    indices.push(base);
-                    indices.push(base + 2);
-                    indices.push(base + 3);
+                    push_quad_indices(&mut indices, quad);
 
-                    batches.push(Batch::Rects(1));
+                    push_batch(&mut batches, Batch::Rects(page, 1));
+                }
+                DisplayItem::RoundRect(RoundRectId(id)) => {
+                    assert!(self.round_rect_buffer.data.contains(*id), "stale {:?}", id);
+
+                    let (page, quad) = page_of(id.index as usize);
+
+                    push_quad_indices(&mut indices, quad);
+
+                    push_batch(&mut batches, Batch::RoundRects(page, 1));
+                }
+                DisplayItem::Border(BorderId(id)) => {
+                    assert!(self.borders.contains(*id), "stale {:?}", id);
+
+                    for quad in self.borders[*id] {
+                        let (page, quad) = page_of(quad.index as usize);
+
+                        push_quad_indices(&mut indices, quad);
+
+                        push_batch(&mut batches, Batch::Borders(page, 1));
+                    }
+                }
+                DisplayItem::BoxShadow(BoxShadowId(id)) => {
+                    assert!(self.box_shadow_buffer.data.contains(*id), "stale {:?}", id);
+
+                    let (page, quad) = page_of(id.index as usize);
+
+                    push_quad_indices(&mut indices, quad);
+
+                    push_batch(&mut batches, Batch::BoxShadows(page, 1));
+                }
+                DisplayItem::Gradient(gradient_id) => {
+                    let gradient = &self.gradients[gradient_id.0];
+                    let (page, quad) = page_of(gradient.quad.index as usize);
+
+                    push_quad_indices(&mut indices, quad);
+
+                    // like images, the same atlas page is enough
+                    push_batch(&mut batches, Batch::Gradients(gradient.ramp.page, page, 1));
                 }
                 DisplayItem::Text(text_id) => {
-                    let text = &self.texts[*text_id];
+                    let text = &self.texts[text_id.0];
+                    let mut n = 0;
 
-                    // TODO: this is static and should be generated with glyphs
-                    for n in 0..text.buffer.data.data.len() {
-                        let base = 4 * (n as VertexIndex);
+                    // TODO: this is static and could be generated with glyphs
+                    for (run, r) in text.runs.iter().enumerate() {
+                        for _ in 0..r.quads {
+                            let (page, quad) = page_of(n);
 
-                        indices.push(base + 1);
-                        indices.push(base);
-                        indices.push(base + 3);
+                            push_quad_indices(&mut indices, quad);
+                            push_batch(&mut batches, Batch::Text(*text_id, run, page, 1));
 
-                        indices.push(base);
-                        indices.push(base + 2);
-                        indices.push(base + 3);
+                            n += 1;
+                        }
                     }
+                }
+                DisplayItem::Image(image_id) => {
+                    let image = &self.images[image_id.0];
+                    let (page, quad) = page_of(image.quad.index as usize);
 
-                    batches.push(Batch::Text(*text_id));
+                    push_quad_indices(&mut indices, quad);
+
+                    // images from the same atlas page can be drawn together
+                    push_batch(&mut batches, Batch::Image(image.alloc.page, page, 1));
                 }
-                _ => unimplemented!()
-            }
-        }
+                DisplayItem::PushClip(clip) => {
+                    let (parent, level) = clips.last().map_or((None, 0), |c| (Some(c.scissor), c.level));
+                    let (a, b) = (Pos(clip.a.0.min(clip.b.0), clip.a.1.min(clip.b.1)), Pos(clip.a.0.max(clip.b.0), clip.a.1.max(clip.b.1)));
+
+                    // empty if they don't overlap
+                    let scissor = match parent {
+                        Some((pa, pb)) => {
+                            let a = Pos(a.0.max(pa.0), a.1.max(pa.1));
+
+                            (a, Pos(b.0.min(pb.0).max(a.0), b.1.min(pb.1).max(a.1)))
+                        }
+                        None => (a, b),
+                    };
+
+                    if !clip.is_rounded() {
+                        set_clip(&mut batches, &mut clip_state, (Some(scissor), stencil_test(level)));
+                        clips.push(ClipEntry { scissor, level, mask: None });
+                        continue;
+                    }
+
+                    // +1 inside of the mask (where the parent's value is), then only there
+                    let quad = self.clip_buffer.add_quad(a, b, RoundRect::new(a, b, clip.radii, RGBA(0, 0, 0, 255)));
+                    let (page, index) = page_of(quad.index as usize);
+
+                    set_clip(&mut batches, &mut clip_state, (Some(scissor), Stencil::Increment(level)));
+                    push_quad_indices(&mut indices, index);
+                    push_batch(&mut batches, Batch::ClipMask(page, 1));
+                    set_clip(&mut batches, &mut clip_state, (Some(scissor), stencil_test(level + 1)));
 
-        unsafe {
-            if !indices.is_empty() {
-                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.index_buffer.vbo);
-                gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * mem::size_of::<VertexIndex>()) as GLsizeiptr, mem::transmute(&indices[0]), gl::STATIC_DRAW);
+                    clips.push(ClipEntry { scissor, level: level + 1, mask: Some(quad) });
+                }
+                DisplayItem::PopClip => {
+                    let clip = clips.pop().expect("PopClip without PushClip");
+
+                    // undo the +1 so that the next clip can use the same value
+                    if let Some(quad) = clip.mask {
+                        let (page, index) = page_of(quad.index as usize);
+
+                        set_clip(&mut batches, &mut clip_state, (Some(clip.scissor), Stencil::Decrement(clip.level)));
+                        push_quad_indices(&mut indices, index);
+                        push_batch(&mut batches, Batch::ClipMask(page, 1));
+                    }
+
+                    let (parent, level) = clips.last().map_or((None, 0), |c| (Some(c.scissor), c.level));
+
+                    set_clip(&mut batches, &mut clip_state, (parent, stencil_test(level)));
+                }
             }
         }
 
+        // unbalanced pushes end with the list (the next frame starts unclipped)
+        set_clip(&mut batches, &mut clip_state, (None, Stencil::Disabled));
+
         self.batches = batches;
 
-        // so that memory is freed one day
-        self.index_buffer.data.data = indices;
+        // uploaded with the next render() so that we don't need
+        // GL context here (and also so that memory is freed one day)
+        self.index_buffer.data = indices;
+        self.index_buffer.dirty = true;
     }
 
     // most of the work has already been done
     // we just need to go through batches, setup pipeline & do indexed draw
     pub fn render(&mut self) {
-        unsafe {
-            gl::ClearColor(1.0, 1.0, 1.0, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT);
-
-            self.upload_buffers();
-
-            // maybe in future something more advanced might happen
-            // but for now it is hard-coded here
-
-            let mut offset: usize = 0;
-
-            for b in &self.batches {
-                let quads_count;
-
-                // println!("batch {:?}", &b);
-
-                match b {
-                    Batch::Rects(num_quads) => {
-                        gl::UseProgram(self.rect_program);
-                        gl::BindBuffer(gl::ARRAY_BUFFER, self.rect_buffer.vbo);
-                        gl::EnableVertexAttribArray(0);
-                        gl::VertexAttribPointer(
-                            0,
-                            2,
-                            gl::FLOAT,
-                            gl::FALSE,
-                            (mem::size_of::<Vertex<RGBA>>()) as GLint,
-                            0 as *const GLvoid,
-                        );
-                        gl::EnableVertexAttribArray(1);
-                        gl::VertexAttribPointer(
-                            1,
-                            4,
-                            gl::UNSIGNED_BYTE,
-                            gl::FALSE,
-                            (mem::size_of::<Vertex<RGBA>>()) as GLint,
-                            (mem::size_of::<Pos>()) as *const std::ffi::c_void,
-                        );
-
-                        quads_count = *num_quads;
-                    },
-                    Batch::Image => {
-                        gl::UseProgram(self.image_program);
-                        quads_count = 1;
-                    },
-                    Batch::Text(text_id) => {
-                        gl::UseProgram(self.text_program);
-                        // TODO: glyph coords/glyph_index
-                        // TODO: uniforms
-
-                        let text = &self.texts[*text_id];
-
-                        gl::BindBuffer(gl::ARRAY_BUFFER, text.buffer.vbo);
-                        gl::EnableVertexAttribArray(0);
-                        gl::VertexAttribPointer(
-                            0,
-                            2,
-                            gl::FLOAT,
-                            gl::FALSE,
-                            (mem::size_of::<Vertex<RGBA>>()) as GLint,
-                            0 as *const GLvoid,
-                        );
-                        gl::EnableVertexAttribArray(1);
-                        gl::VertexAttribPointer(
-                            1,
-                            4,
-                            gl::UNSIGNED_BYTE,
-                            gl::FALSE,
-                            (mem::size_of::<Vertex<RGBA>>()) as GLint,
-                            (mem::size_of::<Pos>()) as *const std::ffi::c_void,
-                        );
-
-                        quads_count = text.buffer.data.data.len();
+        self.backend.clear([1.0, 1.0, 1.0, 1.0]);
+
+        self.upload_buffers();
+
+        let index_buffer = match self.index_buffer.id {
+            Some(id) => id,
+            None => return,
+        };
+
+        // maybe in future something more advanced might happen
+        // but for now it is hard-coded here
+
+        let viewport = self.backend.viewport_size();
+        let scale = Pos(2. / viewport.0 as f32, 2. / viewport.1 as f32);
+
+        let mut offset: usize = 0;
+        let mut current_program = None;
+        let mut current_texture = None;
+
+        for b in &self.batches {
+            // println!("batch {:?}", &b);
+
+            let mut uniforms = Vec::new();
+            let mut texture = None;
+
+            // (usually) one pass
+            let (programs, buffer, quads_count): (&[Program], _, _) = match b {
+                // no indices, just the state for the next batches
+                Batch::Clip(scissor, stencil) => {
+                    self.backend.set_scissor(scissor.map(|(a, b)| to_pixels(a, b, viewport)));
+                    self.backend.set_stencil(*stencil);
+                    continue;
+                }
+                Batch::ClipMask(page, num_quads) => {
+                    uniforms = vec![Uniform::Scale(scale)];
+
+                    (&[Program::ClipMask], self.clip_buffer.page_id(*page), *num_quads)
+                }
+                Batch::Rects(page, num_quads) => (&[Program::Rect], self.rect_buffer.page_id(*page), *num_quads),
+                Batch::RoundRects(page, num_quads) => {
+                    // radii are in pixels
+                    uniforms = vec![Uniform::Scale(scale)];
+
+                    (&[Program::RoundRect], self.round_rect_buffer.page_id(*page), *num_quads)
+                }
+                Batch::Borders(page, num_quads) => {
+                    uniforms = vec![Uniform::Scale(scale)];
+
+                    (&[Program::Border], self.border_buffer.page_id(*page), *num_quads)
+                }
+                Batch::BoxShadows(page, num_quads) => {
+                    uniforms = vec![Uniform::Scale(scale)];
+
+                    (&[Program::BoxShadow], self.box_shadow_buffer.page_id(*page), *num_quads)
+                }
+                Batch::Gradients(atlas_page, page, num_quads) => {
+                    texture = self.gradient_atlas.texture(*atlas_page);
+                    uniforms = vec![Uniform::Scale(scale)];
+
+                    (&[Program::Gradient], self.gradient_buffer.page_id(*page), *num_quads)
+                }
+                Batch::Image(atlas_page, page, num_quads) => {
+                    texture = self.atlas.texture(*atlas_page);
+
+                    (&[Program::Image], self.image_buffer.page_id(*page), *num_quads)
+                }
+                Batch::Text(text_id, run, page, num_quads) => {
+                    // removed while still in the display list, not drawn (like collapsed quads)
+                    if !self.texts.contains(text_id.0) {
+                        offset += 6 * num_quads;
+                        continue;
                     }
+
+                    let text = &self.texts[text_id.0];
+                    let run = &text.runs[*run];
+                    let span = &text.spans[run.span];
+                    let color = if run.kind == RunKind::Background { span.background } else { span.color };
+
+                    uniforms = vec![Uniform::Translate(snap(text.pos, viewport)), Uniform::Scale(scale), Uniform::Color(color)];
+
+                    let cache = match span.mode {
+                        GlyphMode::Coverage => &self.glyph_cache,
+                        GlyphMode::Sdf => &self.sdf_glyph_cache,
+                        GlyphMode::Lcd => &self.lcd_glyph_cache,
+                    };
+
+                    texture = cache.atlas.texture(run.atlas_page);
+
+                    let programs: &[Program] = match span.mode {
+                        _ if run.kind == RunKind::ColorGlyphs => &[Program::ColorText],
+                        GlyphMode::Coverage => &[Program::Text],
+                        GlyphMode::Sdf => {
+                            uniforms.push(Uniform::Smoothing(span.smoothing));
+
+                            &[Program::SdfText]
+                        }
+                        GlyphMode::Lcd => &[Program::LcdTextMask, Program::LcdText],
+                    };
+
+                    (programs, text.buffer.page_id(*page), *num_quads)
                 }
+            };
+
+            // 2 triangles, 6 vertex indices per quad
+            let vertices_count = 6 * quads_count;
 
-                // 2 triangles, 6 vertex indices per quad
-                let vertices_count = 6 * quads_count;
+            if let Some(buffer) = buffer {
+                if let Some(id) = texture {
+                    if texture != current_texture {
+                        self.backend.use_texture(id);
+                        current_texture = texture;
+                    }
+                }
 
-                gl::DrawElements(gl::TRIANGLES, vertices_count as i32, gl::UNSIGNED_SHORT, (offset * std::mem::size_of::<VertexIndex>()) as *const std::ffi::c_void);
+                for &program in programs {
+                    // batches with the same pipeline don't need to set it again
+                    if current_program != Some(program) {
+                        self.backend.use_program(program);
+                        current_program = Some(program);
+                    }
 
-                check();
+                    for u in &uniforms {
+                        self.backend.set_uniform(*u);
+                    }
 
-                // next batch starts right after this one
-                offset += vertices_count;
+                    self.backend.draw_indexed(buffer, index_buffer, offset, vertices_count);
+                }
             }
 
-            check()
+            // next batch starts right after this one
+            offset += vertices_count;
         }
     }
 }
@@ -313,91 +1032,498 @@ impl NotSureWhat {
 struct Quad<T>([Vertex<T>; 4]);
 
 /// Vertex including some primitive-specific attributes
+/// (backends read it as it is, so the layout has to be fixed)
 #[derive(Debug)]
+#[repr(C)]
 struct Vertex<T>(Pos, T);
 
+// the same for all 4 vertices, the fragment shader needs to know
+// the whole rect to get the distance to its (rounded) edge
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct RoundRect {
+    color: RGBA,
+    // a, b
+    bounds: [f32; 4],
+    // x of top-left, top-right, bottom-right, bottom-left & then y of the same
+    radii: [f32; 8],
+}
+
+impl RoundRect {
+    fn new(a: Pos, b: Pos, radii: Radii, color: RGBA) -> Self {
+        let mut data = Self { color, bounds: [a.0, a.1, b.0, b.1], radii: [0.; 8] };
+
+        data.set_radii(radii);
+
+        data
+    }
+
+    fn set_radii(&mut self, radii: Radii) {
+        let Radii { top_left: tl, top_right: tr, bottom_right: br, bottom_left: bl } = radii;
+
+        self.radii = [tl.0, tr.0, br.0, bl.0, tl.1, tr.1, br.1, bl.1];
+    }
+}
+
+// one side of a border, the rest is there for the joins, vertex positions
+// are just (0, 0) or (1, 1), the quad is put along its side in the shader
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct BorderEdge {
+    color: RGBA,
+    // the same as in RoundRect
+    bounds: [f32; 4],
+    radii: [f32; 8],
+    // top, right, bottom, left
+    widths: [f32; 4],
+    // side index (in the same order), style, padding
+    side: [u8; 4],
+}
+
+impl BorderEdge {
+    fn new(a: Pos, b: Pos, border: &Border) -> [Self; 4] {
+        let sides = [border.top, border.right, border.bottom, border.left];
+        let RoundRect { bounds, radii, .. } = RoundRect::new(a, b, border.radii, RGBA(0, 0, 0, 0));
+        let widths = sides.map(|s| s.width);
+
+        [0, 1, 2, 3].map(|i| Self { color: sides[i].color, bounds, radii, widths, side: [i as u8, sides[i].style as u8, 0, 0] })
+    }
+}
+
+// vertex positions are (0, 0) or (1, 1) like in BorderEdge
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct Shadow {
+    color: RGBA,
+    // of the box, the same as in RoundRect
+    bounds: [f32; 4],
+    radii: [f32; 8],
+    // offset x, y, blur, spread
+    params: [f32; 4],
+    // inset, padding
+    inset: [u8; 4],
+}
+
+impl Shadow {
+    fn new(a: Pos, b: Pos, shadow: &BoxShadow) -> Self {
+        let RoundRect { bounds, radii, .. } = RoundRect::new(a, b, shadow.radii, shadow.color);
+
+        Self {
+            color: shadow.color,
+            bounds,
+            radii,
+            params: [shadow.offset.0, shadow.offset.1, shadow.blur, shadow.spread],
+            inset: [shadow.inset as u8, 0, 0, 0],
+        }
+    }
+}
+
+// kind, extend & dither first so that bounds are where
+// they are in RoundRect (the same attribute location)
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct GradientData {
+    // kind, extend, dither, padding
+    flags: [u8; 4],
+    bounds: [f32; 4],
+    // u of the first & the last texel of the ramp, v, padding
+    ramp: [f32; 4],
+    // start & end, center & radii, center & angle
+    geometry: [f32; 4],
+}
+
+impl GradientData {
+    fn new(a: Pos, b: Pos, gradient: &Gradient, ramp: [f32; 4]) -> Self {
+        let (kind, geometry) = match gradient.kind {
+            GradientKind::Linear { start, end } => (0, [start.0, start.1, end.0, end.1]),
+            GradientKind::Radial { center, radii } => (1, [center.0, center.1, radii.0, radii.1]),
+            GradientKind::Conic { center, angle } => (2, [center.0, center.1, angle, 0.]),
+        };
+
+        Self {
+            flags: [kind, gradient.extend as u8, gradient.dither as u8, 0],
+            bounds: [a.0, a.1, b.0, b.1],
+            ramp,
+            geometry,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct GradientRamp {
+    quad: QuadId,
+    ramp: AtlasAlloc,
+}
+
+#[derive(Clone, Copy)]
+struct Image {
+    quad: QuadId,
+    alloc: AtlasAlloc,
+}
+
 struct Text {
     pos: Pos,
+    spans: Vec<SpanStyle>,
+    // glyphs (uvs), in pixels relative to pos
+    buffer: Buffer<Quad<Pos>>,
+    // in the buffer order
+    runs: Vec<TextRun>,
+    // in the glyph caches, released with the text
+    glyphs: Vec<(GlyphMode, GlyphKey)>,
+}
+
+// what's in uniforms, so it can change without touching the buffer
+struct SpanStyle {
     color: RGBA,
-    // TODO: should be Pos (uv for glyph coords)
-    buffer: Buffer<Quad<RGBA>>
+    background: RGBA,
+    mode: GlyphMode,
+    // for sdf
+    smoothing: f32,
 }
 
-// Handles to primitives
-pub type RectId = usize;
-pub type ImageId = usize;
-pub type TextId = usize;
+// quads drawn with the same uniforms & texture
+struct TextRun {
+    kind: RunKind,
+    span: usize,
+    atlas_page: usize,
+    quads: usize,
+}
+
+// in the drawing order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum RunKind {
+    Background,
+    Glyphs,
+    ColorGlyphs,
+    Lines,
+}
+
+/// Slot index + generation, it's not possible to reach another item
+/// (which has been created later in the same slot) with a stale id
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Id {
+    index: u32,
+    generation: u32,
+}
+
+// Handles to primitives, each kind has its own so they can't be mixed up
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RectId(Id);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RoundRectId(Id);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BorderId(Id);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BoxShadowId(Id);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GradientId(Id);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(Id);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextId(Id);
 
 // for indexed drawing
 // raspi can do only 65k vertices in one batch
 // could be configurable but it's probably better to play it safe
 type VertexIndex = u16;
 
+// so every quad buffer is split into pages (separate VBOs) which
+// can be still addressed with VertexIndex
+//
+// TODO(later): 32-bit indices if OES_element_index_uint/desktop GL is available
+const QUADS_PER_PAGE: usize = (VertexIndex::MAX as usize + 1) / 4;
+
+// (page, quad index in that page)
+fn page_of(quad: usize) -> (usize, usize) {
+    (quad / QUADS_PER_PAGE, quad % QUADS_PER_PAGE)
+}
+
+// 2 triangles
+fn push_quad_indices(indices: &mut Vec<VertexIndex>, quad: usize) {
+    let base = 4 * (quad as VertexIndex);
+
+    indices.push(base + 1);
+    indices.push(base);
+    indices.push(base + 3);
+
+    indices.push(base);
+    indices.push(base + 2);
+    indices.push(base + 3);
+}
+
 // one item of what is requested to be drawn
 #[derive(Debug)]
 pub enum DisplayItem {
     Rect(RectId),
+    RoundRect(RoundRectId),
+    Border(BorderId),
+    BoxShadow(BoxShadowId),
+    Gradient(GradientId),
     Image(ImageId),
     Text(TextId),
+    /// everything until the matching `PopClip` is clipped
+    PushClip(Clip),
+    PopClip,
 }
 
 // what is going to be drawn, how many quads so that we know where to start with indices
 // + any other params necessary to setup the pipeline (can be indirect)
+//
+// quad buffers are paged so batches also need to know which page to use
 #[derive(Debug)]
 enum Batch {
-    Rects(usize),
+    // page, quads
+    Rects(usize, usize),
 
-    Text(TextId),
+    // page, quads
+    RoundRects(usize, usize),
+
+    // page, quads
+    Borders(usize, usize),
+
+    // page, quads
+    BoxShadows(usize, usize),
+
+    // atlas page, page, quads
+    Gradients(usize, usize, usize),
+
+    // run, page, quads
+    Text(TextId, usize, usize, usize),
+
+    // atlas page, page, quads
+    Image(usize, usize, usize),
+
+    // scissor (NDC, converted in render() so it follows the viewport), stencil
+    Clip(Option<(Pos, Pos)>, Stencil),
+
+    // page, quads (of clip_buffer)
+    ClipMask(usize, usize),
+}
+
+impl Batch {
+    // extend self if the next batch can be drawn in the same call
+    // (indices are always pushed in order so they are already next to each other)
+    fn fuse(&mut self, next: &Batch) -> bool {
+        match (self, next) {
+            (Batch::Rects(page, quads), Batch::Rects(next_page, next_quads)) if page == next_page => {
+                *quads += next_quads;
+                true
+            }
+            (Batch::RoundRects(page, quads), Batch::RoundRects(next_page, next_quads)) if page == next_page => {
+                *quads += next_quads;
+                true
+            }
+            (Batch::Borders(page, quads), Batch::Borders(next_page, next_quads)) if page == next_page => {
+                *quads += next_quads;
+                true
+            }
+            (Batch::BoxShadows(page, quads), Batch::BoxShadows(next_page, next_quads)) if page == next_page => {
+                *quads += next_quads;
+                true
+            }
+            (Batch::Gradients(atlas_page, page, quads), Batch::Gradients(next_atlas_page, next_page, next_quads)) if atlas_page == next_atlas_page && page == next_page => {
+                *quads += next_quads;
+                true
+            }
+            (Batch::Text(id, run, page, quads), Batch::Text(next_id, next_run, next_page, next_quads)) if id == next_id && run == next_run && page == next_page => {
+                *quads += next_quads;
+                true
+            }
+            (Batch::Image(atlas_page, page, quads), Batch::Image(next_atlas_page, next_page, next_quads)) if atlas_page == next_atlas_page && page == next_page => {
+                *quads += next_quads;
+                true
+            }
+            // nothing was drawn in between, so only the last one matters
+            (Batch::Clip(scissor, stencil), Batch::Clip(next_scissor, next_stencil)) => {
+                *scissor = *next_scissor;
+                *stencil = *next_stencil;
+                true
+            }
+            _ => false
+        }
+    }
+}
+
+// what's in effect after a PushClip (until the matching PopClip)
+struct ClipEntry {
+    // NDC, intersected with the parent
+    scissor: (Pos, Pos),
+    // stencil value inside of the clip
+    level: u8,
+    // quad of the rounded mask (in clip_buffer) so it can be undone
+    mask: Option<QuadId>,
+}
+
+// nested clips without radii keep the parent's stencil value
+fn stencil_test(level: u8) -> Stencil {
+    if level == 0 { Stencil::Disabled } else { Stencil::Test(level) }
+}
+
+// the clip batch is needed only if it's different from what's already set
+fn set_clip(batches: &mut Vec<Batch>, current: &mut (Option<(Pos, Pos)>, Stencil), state: (Option<(Pos, Pos)>, Stencil)) {
+    if *current != state {
+        *current = state;
+        push_batch(batches, Batch::Clip(state.0, state.1));
+    }
+}
+
+// NDC -> (x, y, width, height) in pixels, y goes up
+fn to_pixels(a: Pos, b: Pos, viewport: (usize, usize)) -> [usize; 4] {
+    let x = |x: f32| ((x + 1.) * 0.5 * viewport.0 as f32).round().clamp(0., viewport.0 as f32) as usize;
+    let y = |y: f32| ((y + 1.) * 0.5 * viewport.1 as f32).round().clamp(0., viewport.1 as f32) as usize;
+
+    [x(a.0), y(a.1), x(b.0) - x(a.0), y(b.1) - y(a.1)]
+}
+
+fn push_batch(batches: &mut Vec<Batch>, batch: Batch) {
+    if let Some(last) = batches.last_mut() {
+        if last.fuse(&batch) {
+            return;
+        }
+    }
 
-    // always one quad
-    // TODO: TextureId or ImageId + self.images
-    Image,
+    batches.push(batch);
 }
 
+// items are quads, split into pages of QUADS_PER_PAGE
+//
+// changes are tracked so that we can upload just the dirty parts,
+// the whole page is re-uploaded only if it doesn't fit anymore (and then
+// it's twice as big so that appending is cheap too)
 struct Buffer<T> {
-    // TODO: rename
-    vbo: VboId,
-    data: LeakyVec<T>
+    // created lazily, on the first upload
+    pages: Vec<Page>,
+    data: SlotMap<T>,
+    // quad ranges, not necessarily sorted/disjoint
+    dirty: Vec<Range<usize>>,
+}
+
+struct Page {
+    id: BufferId,
+    // how many quads the GL buffer can hold
+    capacity: usize,
 }
 
+// if there are too many changes, it's cheaper to upload all of them at once
+const MAX_DIRTY_RANGES: usize = 32;
+
 impl <T> Buffer<T> {
     fn new() -> Self {
-        let mut vbo = 0;
+        Self {
+            pages: Vec::new(),
+            data: SlotMap::new(),
+            dirty: Vec::new(),
+        }
+    }
 
-        unsafe { gl::GenBuffers(1, &mut vbo) }
+    // for buffers which are rebuilt every time, pages are kept (and re-uploaded)
+    fn clear(&mut self) {
+        self.data = SlotMap::new();
+        self.dirty.clear();
+    }
 
-        Self {
-            vbo,
-            data: LeakyVec::new()
+    fn page_id(&self, page: usize) -> Option<BufferId> {
+        self.pages.get(page).map(|p| p.id)
+    }
+
+    fn mark_dirty(&mut self, quad: usize) {
+        // typically we are changing the same or the next one
+        if let Some(last) = self.dirty.last_mut() {
+            if last.start <= quad && quad <= last.end {
+                last.end = last.end.max(quad + 1);
+                return;
+            }
+        }
+
+        if self.dirty.len() == MAX_DIRTY_RANGES {
+            let start = self.dirty.iter().map(|r| r.start).min().unwrap().min(quad);
+            let end = self.dirty.iter().map(|r| r.end).max().unwrap().max(quad + 1);
+
+            self.dirty.clear();
+            self.dirty.push(start..end);
+            return;
         }
+
+        self.dirty.push(quad..quad + 1);
     }
 
-    fn remove(&mut self, id: usize) {
-        self.data.remove(id);
+    fn upload(&mut self, backend: &mut impl Backend) {
+        if self.dirty.is_empty() {
+            return;
+        }
+
+        // println!("upload {:?} x {}b", &self.dirty, mem::size_of::<T>());
+
+        let item_size = mem::size_of::<T>();
+
+        for (page, quads) in self.data.data.chunks(QUADS_PER_PAGE).enumerate() {
+            if page == self.pages.len() {
+                self.pages.push(Page { id: backend.create_buffer(), capacity: 0 });
+            }
+
+            let p = &mut self.pages[page];
+
+            if quads.len() > p.capacity {
+                p.capacity = quads.len().max(2 * p.capacity).min(QUADS_PER_PAGE);
+
+                let mut data = as_bytes(quads).to_vec();
+                data.resize(p.capacity * item_size, 0);
+
+                backend.upload(p.id, BufferKind::Vertex, &data);
+                continue;
+            }
+
+            let page_start = page * QUADS_PER_PAGE;
+
+            // appended quads are dirty too
+            for r in &self.dirty {
+                let start = r.start.max(page_start) - page_start;
+                let end = r.end.min(page_start + quads.len()).max(page_start) - page_start;
+
+                if start < end {
+                    backend.upload_range(p.id, BufferKind::Vertex, start * item_size, as_bytes(&quads[start..end]));
+                }
+            }
+        }
+
+        self.dirty.clear();
     }
+}
 
-    fn upload(&self) {
-      if self.data.data.is_empty() {
-          return;
-      }
+// indices are not paged, there's just one buffer for everything
+// and it's always replaced as a whole
+struct IndexBuffer {
+    id: Option<BufferId>,
+    data: Vec<VertexIndex>,
+    dirty: bool,
+}
 
-      let item_size = mem::size_of::<T>();
+impl IndexBuffer {
+    fn new() -> Self {
+        Self {
+            id: None,
+            data: Vec::new(),
+            dirty: false,
+        }
+    }
 
-      // println!("upload {} x {}b", self.data.data.len(), item_size);
+    fn upload(&mut self, backend: &mut impl Backend) {
+        if !self.dirty || self.data.is_empty() {
+            return;
+        }
+
+        self.dirty = false;
 
-      unsafe {
-          gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-          gl::BufferData(
-            gl::ARRAY_BUFFER,
-            // 4 vertices per quad
-            (4 * self.data.data.len() * item_size) as isize,
-            mem::transmute(&self.data.data[0]),
-            gl::STATIC_DRAW
-          );
+        let id = *self.id.get_or_insert_with(|| backend.create_buffer());
 
-          check();
-      }
-  }
+        backend.upload(id, BufferKind::Index, as_bytes(&self.data));
+    }
 }
 
 impl <T: Copy> Buffer<Quad<T>> {
@@ -409,7 +1535,11 @@ impl <T: Copy> Buffer<Quad<T>> {
             Vertex(b, data),
         ]);
 
-        self.data.add(quad)
+        let id = self.data.add(quad);
+
+        self.mark_dirty(id.index as usize);
+
+        id
     }
 
     fn set_quad_bounds(&mut self, id: QuadId, a: Pos, b: Pos) {
@@ -419,220 +1549,193 @@ impl <T: Copy> Buffer<Quad<T>> {
         q.0[1].0 = Pos(b.0, a.1);
         q.0[2].0 = Pos(a.0, b.1);
         q.0[3].0 = b;
-    }
-}
 
-type VboId = u32;
-type QuadId = usize;
+        self.mark_dirty(id.index as usize);
+    }
 
+    fn set_quad_data(&mut self, id: QuadId, data: T) {
+        for v in &mut self.data[id].0 {
+            v.1 = data;
+        }
 
-const RECT_VS: &str = r#"
-  #version 100
+        self.mark_dirty(id.index as usize);
+    }
 
-  attribute vec2 a_pos;
-  attribute vec4 a_color;
+    fn remove(&mut self, id: QuadId) {
+        // collapse it so it's not visible even if it's still in the display list
+        self.set_quad_bounds(id, Pos(0., 0.), Pos(0., 0.));
 
-  varying vec4 v_color;
+        self.data.remove(id);
+    }
+}
 
-  void main() {
-    gl_Position = vec4(a_pos, 0.0, 1.0);
-    v_color = a_color;
-  }
-"#;
+impl Buffer<Quad<Pos>> {
+    // uvs interpolated from a to b
+    fn add_quad_uv(&mut self, a: Pos, b: Pos, uv_a: Pos, uv_b: Pos) -> QuadId {
+        let id = self.add_quad(a, b, uv_a);
 
-const RECT_FS: &str = r#"
-  #version 100
+        self.set_quad_uv(id, uv_a, uv_b);
 
-  precision mediump float;
+        id
+    }
 
-  varying vec4 v_color;
+    fn set_quad_uv(&mut self, id: QuadId, uv_a: Pos, uv_b: Pos) {
+        let q = &mut self.data[id];
 
-  void main() {
-    gl_FragColor = v_color / 256.;
-  }
-"#;
+        q.0[0].1 = uv_a;
+        q.0[1].1 = Pos(uv_b.0, uv_a.1);
+        q.0[2].1 = Pos(uv_a.0, uv_b.1);
+        q.0[3].1 = uv_b;
 
-// TODO:
-// - sample from texture
-const IMAGE_VS: &str = RECT_VS;
-const IMAGE_FS: &str = RECT_FS;
+        self.mark_dirty(id.index as usize);
+    }
+}
 
-// TODO:
-// - translate glyphs by uniform
-// - sample from texture (uv attr or glyph_index)
-const TEXT_VS: &str = r#"
-  #version 100
+type QuadId = Id;
 
-  attribute vec2 a_pos;
-  attribute vec4 a_color;
+// (bottom-left, top-right), atlas has the top row first
+fn quad_uv(atlas: &Atlas, alloc: AtlasAlloc) -> (Pos, Pos) {
+    let ((u0, v0), (u1, v1)) = atlas.uv(alloc);
 
-  varying vec4 v_color;
+    (Pos(u0, v1), Pos(u1, v0))
+}
 
-  void main() {
-    gl_Position = vec4(a_pos, 0.0, 1.0);
-    v_color = a_color;
-  }
-"#;
+// centers of the first & the last texel (so that LINEAR doesn't mix in the neighbours)
+fn ramp_uv(atlas: &Atlas, alloc: AtlasAlloc) -> [f32; 4] {
+    let ((u0, v0), (u1, v1)) = atlas.uv(alloc);
+    let texel = (u1 - u0) / alloc.width as f32;
 
-const TEXT_FS: &str = r#"
-  #version 100
+    [u0 + texel / 2., u1 - texel / 2., (v0 + v1) / 2., 0.]
+}
 
-  precision mediump float;
+// to the closest pixel
+fn snap(pos: Pos, viewport: (usize, usize)) -> Pos {
+    let (w, h) = (viewport.0 as f32, viewport.1 as f32);
 
-  varying vec4 v_color;
+    Pos(
+        ((pos.0 + 1.) * 0.5 * w).round() / (0.5 * w) - 1.,
+        ((pos.1 + 1.) * 0.5 * h).round() / (0.5 * h) - 1.,
+    )
+}
 
-  void main() {
-    gl_FragColor = v_color / 256.;
-  }
-"#;
+// 2048 is the max. for raspi, 1024 should be enough for UI things
+const ATLAS_PAGE_SIZE: usize = 1024;
 
-unsafe fn check() {
-    let err = gl::GetError();
-    if err != gl::NO_ERROR {
-        panic!("gl err {}", err);
-    }
+fn as_bytes<T>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) }
 }
 
-// from gleam
-fn get_shader_info_log(shader: GLuint) -> String {
-    let mut max_len = [0];
-    unsafe {
-        get_shader_iv(shader, gl::INFO_LOG_LENGTH, &mut max_len);
-    }
-    if max_len[0] == 0 {
-        return String::new();
-    }
-    let mut result = vec![0u8; max_len[0] as usize];
-    let mut result_len = 0 as GLsizei;
-    unsafe {
-        gl::GetShaderInfoLog(
-            shader,
-            max_len[0] as GLsizei,
-            &mut result_len,
-            result.as_mut_ptr() as *mut GLchar,
-        );
-    }
-    result.truncate(if result_len > 0 {
-        result_len as usize
-    } else {
-        0
-    });
-    String::from_utf8(result).unwrap()
-}
-unsafe fn get_shader_iv(shader: GLuint, pname: GLenum, result: &mut [GLint]) {
-    assert!(!result.is_empty());
-    gl::GetShaderiv(shader, pname, result.as_mut_ptr());
-}
-fn get_program_info_log(program: GLuint) -> String {
-    let mut max_len = [0];
-    unsafe {
-        get_program_iv(program, gl::INFO_LOG_LENGTH, &mut max_len);
-    }
-    if max_len[0] == 0 {
-        return String::new();
-    }
-    let mut result = vec![0u8; max_len[0] as usize];
-    let mut result_len = 0 as GLsizei;
-    unsafe {
-        gl::GetProgramInfoLog(
-            program,
-            max_len[0] as GLsizei,
-            &mut result_len,
-            result.as_mut_ptr() as *mut GLchar,
-        );
-    }
-    result.truncate(if result_len > 0 {
-        result_len as usize
-    } else {
-        0
-    });
-    String::from_utf8(result).unwrap()
-}
-unsafe fn get_program_iv(program: GLuint, pname: GLenum, result: &mut [GLint]) {
-    assert!(!result.is_empty());
-    gl::GetProgramiv(program, pname, result.as_mut_ptr());
+
+// some store with stable ids
+//
+// removed slots are reused (but the values are left in place until then
+// so that the data stay contiguous & can be uploaded as they are)
+// and generations make sure that stale ids are not going to hit the new item
+//
+// allocation is costy so maybe we should reuse buffers too
+// (not just space for their triple but also their data buffer)
+struct SlotMap<T> {
+    data: Vec<T>,
+    generations: Vec<u32>,
+    free: Vec<usize>,
 }
 
-unsafe fn shader_program(vertex_shader_source: &str, fragment_shader_source: &str) -> u32 {
-    let vertex_shader = shader(gl::VERTEX_SHADER, vertex_shader_source);
-    let fragment_shader = shader(gl::FRAGMENT_SHADER, fragment_shader_source);
+impl <T> SlotMap<T> {
+    fn new() -> Self {
+        Self { data: Vec::new(), generations: Vec::new(), free: Vec::new() }
+    }
 
-    let program = gl::CreateProgram();
-    gl::AttachShader(program, vertex_shader);
-    gl::AttachShader(program, fragment_shader);
-    gl::LinkProgram(program);
+    fn add(&mut self, item: T) -> Id {
+        match self.free.pop() {
+            Some(index) => {
+                self.data[index] = item;
 
-    let mut success = gl::FALSE as GLint;
+                Id { index: index as u32, generation: self.generations[index] }
+            }
+            None => {
+                self.data.push(item);
+                self.generations.push(0);
 
-    gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+                Id { index: (self.data.len() - 1) as u32, generation: 0 }
+            }
+        }
+    }
 
-    if success != gl::TRUE as GLint {
-        panic!(get_program_info_log(program));
+    fn contains(&self, id: Id) -> bool {
+        self.generations.get(id.index as usize) == Some(&id.generation)
     }
 
-    gl::DeleteShader(vertex_shader);
-    gl::DeleteShader(fragment_shader);
+    fn remove(&mut self, id: Id) {
+        assert!(self.contains(id), "stale {:?}", id);
 
-    program
+        // invalidate all copies of the id
+        self.generations[id.index as usize] = self.generations[id.index as usize].wrapping_add(1);
+        self.free.push(id.index as usize);
+    }
 }
 
-unsafe fn shader(shader_type: u32, source: &str) -> u32 {
-    let shader = gl::CreateShader(shader_type);
+impl <T> std::ops::Index<Id> for SlotMap<T> {
+    type Output = T;
 
-    gl::ShaderSource(
-        shader,
-        1,
-        &(CString::new(source.as_bytes()).expect("get CString")).as_ptr(),
-        ptr::null(),
-    );
-    gl::CompileShader(shader);
+    fn index(&self, id: Id) -> &T {
+        assert!(self.contains(id), "stale {:?}", id);
 
-    let mut success = gl::FALSE as GLint;
+        &self.data[id.index as usize]
+    }
+}
 
-    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+impl <T> std::ops::IndexMut<Id> for SlotMap<T> {
+    fn index_mut(&mut self, id: Id) -> &mut T {
+        assert!(self.contains(id), "stale {:?}", id);
 
-    if success != gl::TRUE as GLint {
-        panic!(get_shader_info_log(shader));
+        &mut self.data[id.index as usize]
     }
-
-    shader
 }
 
-// some store with stable ids
-// TODO: freelist or something (now it just leaks memory)
-//
-// allocation is costy so maybe we should reuse buffers too
-// (not just space for their triple but also their data buffer)
-struct LeakyVec<T> {
-    data: Vec<T>
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl <T> LeakyVec<T> {
-    fn new() -> Self {
-        Self { data: Vec::new() }
-    }
+    #[test]
+    fn slot_map() {
+        let mut map = SlotMap::new();
+        let a = map.add("a");
+        let b = map.add("b");
 
-    fn add(&mut self, item: T) -> usize {
-        let id = self.data.len();
+        map.remove(a);
 
-        self.data.push(item);
+        assert!(!map.contains(a));
+        assert!(map.contains(b));
 
-        id
+        // the slot is reused with a new generation
+        let c = map.add("c");
+
+        assert_eq!((c.index, c.generation), (a.index, a.generation + 1));
+        assert!(!map.contains(a));
+        assert_eq!(map[c], "c");
+        assert_eq!(map[b], "b");
     }
 
-    fn remove(&mut self, id: usize) {}
-}
+    #[test]
+    #[should_panic(expected = "stale")]
+    fn stale() {
+        let mut map = SlotMap::new();
+        let a = map.add(1);
 
-impl <T> std::ops::Index<usize> for LeakyVec<T> {
-    type Output = T;
+        map.remove(a);
+        map.add(2);
 
-    fn index(&self, key: usize) -> &T {
-        &self.data[key]
+        let _ = map[a];
     }
-}
 
-impl <T> std::ops::IndexMut<usize> for LeakyVec<T> {
-    fn index_mut(&mut self, key: usize) -> &mut T {
-        &mut self.data[key]
+    #[test]
+    fn generation_wraps() {
+        let mut map = SlotMap::new();
+        let a = map.add(1);
+
+        map.generations[a.index as usize] = u32::MAX;
+        map.remove(Id { generation: u32::MAX, ..a });
+
+        assert_eq!(map.add(2).generation, 0);
     }
 }
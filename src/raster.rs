@@ -0,0 +1,1124 @@
+// CPU fallback for boxes without GPU (and for pixel-exact tests)
+//
+// it gets the very same buffers & draw calls as `GlBackend` and
+// rasterizes the triangles with the same math our shaders do
+// (color / 256, NEAREST sampling, SRC_ALPHA + ONE_MINUS_SRC_ALPHA blending)
+
+use std::f32::consts::{FRAC_1_SQRT_2, TAU};
+use std::mem;
+use std::ptr;
+
+use super::{BorderEdge, GradientData, Pos, RGBA, RoundRect, Shadow, Vertex, VertexIndex};
+use super::backend::*;
+
+/// Renders into `framebuffer` instead of the screen
+pub struct SoftwareBackend {
+    pub framebuffer: Framebuffer,
+
+    buffers: Vec<Vec<u8>>,
+    textures: Vec<Texture>,
+    current: Program,
+    texture: TextureId,
+    translate: Pos,
+    scale: Pos,
+    color: RGBA,
+    smoothing: f32,
+    scissor: Option<[usize; 4]>,
+    stencil: Stencil,
+}
+
+struct Texture {
+    filter: TextureFilter,
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Texture {
+    fn new(filter: TextureFilter) -> Self {
+        Self {
+            filter,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        }
+    }
+
+    // CLAMP_TO_EDGE
+    fn sample(&self, uv: Pos) -> [f32; 4] {
+        if self.pixels.is_empty() {
+            return [0., 0., 0., 0.];
+        }
+
+        let (x, y) = (uv.0 * self.width as f32, uv.1 * self.height as f32);
+
+        match self.filter {
+            TextureFilter::Nearest => self.texel(x as isize, y as isize),
+            TextureFilter::Linear => {
+                // texel centers are at .5
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as isize, y0 as isize);
+
+                let lerp = |a: [f32; 4], b: [f32; 4], t: f32| [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t);
+                let top = lerp(self.texel(x0, y0), self.texel(x0 + 1, y0), fx);
+                let bottom = lerp(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), fx);
+
+                lerp(top, bottom, fy)
+            }
+        }
+    }
+
+    fn texel(&self, x: isize, y: isize) -> [f32; 4] {
+        let x = x.max(0).min(self.width as isize - 1) as usize;
+        let y = y.max(0).min(self.height as isize - 1) as usize;
+        let p = &self.pixels[4 * (y * self.width + x)..];
+
+        [p[0] as f32 / 255., p[1] as f32 / 255., p[2] as f32 / 255., p[3] as f32 / 255.]
+    }
+}
+
+impl SoftwareBackend {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            framebuffer: Framebuffer::new(width, height),
+
+            buffers: Vec::new(),
+            textures: Vec::new(),
+            current: Program::Rect,
+            texture: 0,
+            translate: Pos(0., 0.),
+            scale: Pos(1., 1.),
+            color: RGBA(0, 0, 0, 0),
+            smoothing: 0.,
+            scissor: None,
+            stencil: Stencil::Disabled,
+        }
+    }
+}
+
+impl Backend for SoftwareBackend {
+    fn create_buffer(&mut self) -> BufferId {
+        self.buffers.push(Vec::new());
+
+        self.buffers.len() as BufferId
+    }
+
+    // ids are not reused, so just free the memory
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        self.buffers[buffer as usize - 1] = Vec::new();
+    }
+
+    fn upload(&mut self, buffer: BufferId, kind: BufferKind, data: &[u8]) {
+        self.buffers[buffer as usize - 1] = data.to_vec();
+    }
+
+    fn upload_range(&mut self, buffer: BufferId, kind: BufferKind, offset: usize, data: &[u8]) {
+        self.buffers[buffer as usize - 1][offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn create_texture(&mut self, filter: TextureFilter) -> TextureId {
+        self.textures.push(Texture::new(filter));
+
+        self.textures.len() as TextureId
+    }
+
+    fn delete_texture(&mut self, texture: TextureId) {
+        self.textures[texture as usize - 1].pixels = Vec::new();
+    }
+
+    fn upload_texture(&mut self, texture: TextureId, width: usize, height: usize, pixels: &[u8]) {
+        let t = &mut self.textures[texture as usize - 1];
+
+        t.width = width;
+        t.height = height;
+        t.pixels = pixels.to_vec();
+    }
+
+    fn upload_texture_region(&mut self, texture: TextureId, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]) {
+        let t = &mut self.textures[texture as usize - 1];
+
+        for row in 0..height {
+            let dst = 4 * ((y + row) * t.width + x);
+
+            t.pixels[dst..dst + 4 * width].copy_from_slice(&pixels[4 * row * width..4 * (row + 1) * width]);
+        }
+    }
+
+    fn use_texture(&mut self, texture: TextureId) {
+        self.texture = texture;
+    }
+
+    fn use_program(&mut self, program: Program) {
+        self.current = program;
+    }
+
+    fn set_uniform(&mut self, uniform: Uniform) {
+        match uniform {
+            Uniform::Translate(pos) => self.translate = pos,
+            Uniform::Scale(scale) => self.scale = scale,
+            Uniform::Color(color) => self.color = color,
+            Uniform::Smoothing(smoothing) => self.smoothing = smoothing,
+        }
+    }
+
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId, offset: usize, count: usize) {
+        let vertices = &self.buffers[vertices as usize - 1];
+        let indices = &self.buffers[indices as usize - 1];
+        let texture = self.textures.get((self.texture as usize).wrapping_sub(1));
+        let framebuffer = &mut self.framebuffer;
+
+        let (program, translate, scale, color, smoothing) = (self.current, self.translate, self.scale, self.color, self.smoothing);
+        let (scissor, stencil) = (self.scissor, self.stencil);
+
+        // a_pos is always first, then color or uv
+        let stride = match program {
+            Program::Rect => mem::size_of::<Vertex<RGBA>>(),
+            Program::RoundRect | Program::ClipMask => mem::size_of::<Vertex<RoundRect>>(),
+            Program::Border => mem::size_of::<Vertex<BorderEdge>>(),
+            Program::BoxShadow => mem::size_of::<Vertex<Shadow>>(),
+            Program::Gradient => mem::size_of::<Vertex<GradientData>>(),
+            _ => mem::size_of::<Vertex<Pos>>(),
+        };
+
+        // vertex shaders, position & varying
+        let vertex = |i: usize| {
+            let v = &vertices[i * stride..];
+
+            match program {
+                Program::Rect => {
+                    let Vertex(pos, c) = read_vertex::<RGBA>(v);
+
+                    (pos, [c.0 as f32, c.1 as f32, c.2 as f32, c.3 as f32])
+                }
+                // pixels relative to the center
+                Program::RoundRect | Program::ClipMask => {
+                    let Vertex(pos, data) = read_vertex::<RoundRect>(v);
+
+                    (pos, local(pos, data.bounds, scale))
+                }
+                Program::Border => border_vertex(&read_vertex(v), scale),
+                Program::BoxShadow => box_shadow_vertex(&read_vertex(v), scale),
+                Program::Gradient => {
+                    let Vertex(pos, data) = read_vertex::<GradientData>(v);
+
+                    (pos, local(pos, data.bounds, scale))
+                }
+                Program::Image => {
+                    let Vertex(pos, uv) = read_vertex::<Pos>(v);
+
+                    (pos, [uv.0, uv.1, 0., 0.])
+                }
+                _ => {
+                    let Vertex(pos, uv) = read_vertex::<Pos>(v);
+
+                    (Pos(translate.0 + pos.0 * scale.0, translate.1 + pos.1 * scale.1), [uv.0, uv.1, 0., 0.])
+                }
+            }
+        };
+
+        // fragment shaders, attributes which are the same in all vertices
+        // (of the primitive) are read directly from the first one
+        let fragment = |v: [f32; 4], flat: &[u8], frag_coord: (f32, f32)| match program {
+            Program::Rect => [v[0] / 256., v[1] / 256., v[2] / 256., v[3] / 256.],
+            Program::RoundRect => {
+                let Vertex(_, data) = read_vertex::<RoundRect>(flat);
+                let c = data.color;
+                let size = size(data.bounds, scale);
+                let distance = round_rect_distance((v[0], v[1]), (size.0 / 2., size.1 / 2.), fit_radii(size, data.radii));
+                let coverage = (0.5 - distance).clamp(0., 1.);
+
+                [c.0 as f32 / 256., c.1 as f32 / 256., c.2 as f32 / 256., c.3 as f32 * coverage / 256.]
+            }
+            // alpha is only for the discard
+            Program::ClipMask => {
+                let Vertex(_, data) = read_vertex::<RoundRect>(flat);
+                let size = size(data.bounds, scale);
+                let distance = round_rect_distance((v[0], v[1]), (size.0 / 2., size.1 / 2.), fit_radii(size, data.radii));
+
+                [0., 0., 0., if distance > 0. { 0. } else { 1. }]
+            }
+            Program::Border => {
+                let Vertex(_, data) = read_vertex::<BorderEdge>(flat);
+                let c = data.color;
+                let coverage = border_coverage((v[0], v[1]), &data, scale);
+
+                [c.0 as f32 / 256., c.1 as f32 / 256., c.2 as f32 / 256., c.3 as f32 * coverage / 256.]
+            }
+            Program::BoxShadow => {
+                let Vertex(_, data) = read_vertex::<Shadow>(flat);
+                let c = data.color;
+                let coverage = box_shadow_coverage((v[0], v[1]), &data, scale);
+
+                [c.0 as f32 / 256., c.1 as f32 / 256., c.2 as f32 / 256., c.3 as f32 * coverage / 256.]
+            }
+            Program::Gradient => {
+                let Vertex(_, data) = read_vertex::<GradientData>(flat);
+                let [u0, u1, ramp_v, _] = data.ramp;
+                let t = gradient_t((v[0], v[1]), &data);
+                let mut c = texture.map(|tex| tex.sample(Pos(u0 + (u1 - u0) * t, ramp_v))).unwrap_or([0.; 4]);
+
+                if data.flags[2] != 0 {
+                    let noise = (52.982_918 * (frag_coord.0 * 0.06711056 + frag_coord.1 * 0.00583715).fract()).fract();
+
+                    for c in &mut c[..3] {
+                        *c += (noise - 0.5) / 32.;
+                    }
+                }
+
+                c
+            }
+            Program::Image => texture.map(|t| t.sample(Pos(v[0], v[1]))).unwrap_or([0.; 4]),
+            Program::Text => {
+                let coverage = texture.map(|t| t.sample(Pos(v[0], v[1]))[3]).unwrap_or(0.);
+
+                [color.0 as f32 / 256., color.1 as f32 / 256., color.2 as f32 / 256., color.3 as f32 * coverage / 256.]
+            }
+            Program::SdfText => {
+                let distance = texture.map(|t| t.sample(Pos(v[0], v[1]))[3]).unwrap_or(0.);
+                let coverage = smoothstep(0.5 - smoothing, 0.5 + smoothing, distance);
+
+                [color.0 as f32 / 256., color.1 as f32 / 256., color.2 as f32 / 256., color.3 as f32 * coverage / 256.]
+            }
+            Program::LcdTextMask => {
+                let c = texture.map(|t| t.sample(Pos(v[0], v[1]))).unwrap_or([0.; 4]);
+                let a = color.3 as f32 / 256.;
+
+                [c[0] * a, c[1] * a, c[2] * a, 0.]
+            }
+            Program::LcdText => {
+                let c = texture.map(|t| t.sample(Pos(v[0], v[1]))).unwrap_or([0.; 4]);
+                let a = color.3 as f32 / 256.;
+
+                [color.0 as f32 / 256. * c[0] * a, color.1 as f32 / 256. * c[1] * a, color.2 as f32 / 256. * c[2] * a, 0.]
+            }
+            Program::ColorText => {
+                let c = texture.map(|t| t.sample(Pos(v[0], v[1]))).unwrap_or([0.; 4]);
+
+                [c[0], c[1], c[2], c[3] * color.3 as f32 / 256.]
+            }
+        };
+
+        // like the BlendFunc() in GlBackend::use_program()
+        let blend = match program {
+            Program::LcdTextMask => Blend::Mask,
+            Program::LcdText => Blend::Add,
+            Program::ClipMask => Blend::Stencil,
+            _ => Blend::Alpha,
+        };
+
+        let index = |i: usize| {
+            let i = (offset + i) * mem::size_of::<VertexIndex>();
+
+            VertexIndex::from_ne_bytes([indices[i], indices[i + 1]]) as usize
+        };
+
+        for t in (0..count).step_by(3) {
+            let flat = &vertices[index(t) * stride..];
+
+            framebuffer.fill_triangle([vertex(index(t)), vertex(index(t + 1)), vertex(index(t + 2))], &|v, frag_coord| fragment(v, flat, frag_coord), blend, scissor, stencil);
+        }
+    }
+
+    fn set_scissor(&mut self, rect: Option<[usize; 4]>) {
+        self.scissor = rect;
+    }
+
+    fn set_stencil(&mut self, stencil: Stencil) {
+        self.stencil = stencil;
+    }
+
+    fn clear(&mut self, color: [f32; 4]) {
+        self.framebuffer.clear(color);
+    }
+
+    fn viewport_size(&self) -> (usize, usize) {
+        (self.framebuffer.width, self.framebuffer.height)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Blend {
+    // SRC_ALPHA, ONE_MINUS_SRC_ALPHA
+    Alpha,
+    // ZERO, ONE_MINUS_SRC_COLOR
+    Mask,
+    // ONE, ONE
+    Add,
+    // ColorMask(false), only the stencil is updated & alpha < 0.5 is discarded
+    Stencil,
+}
+
+/// RGBA8 pixels, top row first (unlike `glReadPixels`)
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    // one byte per pixel, top row first too
+    stencil: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+            stencil: vec![0; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> RGBA {
+        let i = 4 * (y * self.width + x);
+        let p = &self.pixels[i..i + 4];
+
+        RGBA(p[0], p[1], p[2], p[3])
+    }
+
+    fn clear(&mut self, color: [f32; 4]) {
+        let color = color.iter().map(|c| to_u8(*c)).collect::<Vec<_>>();
+
+        for p in self.pixels.chunks_mut(4) {
+            p.copy_from_slice(&color);
+        }
+
+        self.stencil.fill(0);
+    }
+
+    fn blend(&mut self, x: usize, y: usize, src: [f32; 4], blend: Blend) {
+        let i = 4 * (y * self.width + x);
+        let a = src[3];
+
+        for (p, src) in self.pixels[i..i + 4].iter_mut().zip(src) {
+            let dst = *p as f32 / 255.;
+
+            *p = to_u8(match blend {
+                Blend::Alpha => src * a + dst * (1. - a),
+                Blend::Mask => dst * (1. - src),
+                Blend::Add => src + dst,
+                Blend::Stencil => dst,
+            });
+        }
+    }
+
+    // NDC -> pixels, y goes down
+    fn to_screen(&self, pos: Pos) -> (f32, f32) {
+        (
+            (pos.0 + 1.) * 0.5 * self.width as f32,
+            (1. - pos.1) * 0.5 * self.height as f32,
+        )
+    }
+
+    // vertices are (position, varying), varyings are interpolated for each pixel
+    // fragment gets also gl_FragCoord (y goes up)
+    fn fill_triangle(&mut self, vertices: [(Pos, [f32; 4]); 3], fragment: &dyn Fn([f32; 4], (f32, f32)) -> [f32; 4], blend: Blend, scissor: Option<[usize; 4]>, stencil: Stencil) {
+        let mut p = [
+            self.to_screen(vertices[0].0),
+            self.to_screen(vertices[1].0),
+            self.to_screen(vertices[2].0),
+        ];
+        let mut v = [vertices[0].1, vertices[1].1, vertices[2].1];
+
+        let mut area = edge(p[0], p[1], p[2]);
+
+        if area == 0. {
+            return;
+        }
+
+        // keep one winding so that the edge test below works for both
+        if area < 0. {
+            p.swap(1, 2);
+            v.swap(1, 2);
+            area = -area;
+        }
+
+        let mut min_x = p.iter().map(|p| p.0).fold(f32::INFINITY, f32::min).max(0.) as usize;
+        let mut min_y = p.iter().map(|p| p.1).fold(f32::INFINITY, f32::min).max(0.) as usize;
+        let mut max_x = (p.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max).ceil() as usize).min(self.width);
+        let mut max_y = (p.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max).ceil() as usize).min(self.height);
+
+        // scissor y goes up
+        if let Some([x, y, width, height]) = scissor {
+            let top = self.height.saturating_sub(y + height);
+
+            min_x = min_x.max(x);
+            max_x = max_x.min(x + width);
+            min_y = min_y.max(top);
+            max_y = max_y.min(self.height.saturating_sub(y));
+        }
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                // sample in the pixel center, like GL does
+                let s = (x as f32 + 0.5, y as f32 + 0.5);
+
+                let w0 = shared_edge(p[1], p[2], s);
+                let w1 = shared_edge(p[2], p[0], s);
+                let w2 = shared_edge(p[0], p[1], s);
+
+                // top-left rule, shared edges (quad diagonal) are not blended twice
+                if !covers(w0, p[1], p[2]) || !covers(w1, p[2], p[0]) || !covers(w2, p[0], p[1]) {
+                    continue;
+                }
+
+                let (w0, w1, w2) = (w0 / area, w1 / area, w2 / area);
+                let mut varying = [0.; 4];
+
+                for i in 0..4 {
+                    varying[i] = w0 * v[0][i] + w1 * v[1][i] + w2 * v[2][i];
+                }
+
+                // EQUAL
+                let stencil_index = y * self.width + x;
+
+                match stencil {
+                    Stencil::Test(v) | Stencil::Increment(v) | Stencil::Decrement(v) if self.stencil[stencil_index] != v => continue,
+                    _ => {}
+                }
+
+                let frag_coord = (s.0, self.height as f32 - s.1);
+                let color = fragment(varying, frag_coord);
+
+                match blend {
+                    Blend::Stencil if color[3] < 0.5 => continue,
+                    Blend::Stencil => {}
+                    _ => self.blend(x, y, color, blend),
+                }
+
+                // INCR & DECR are clamped
+                match stencil {
+                    Stencil::Increment(_) => self.stencil[stencil_index] = self.stencil[stencil_index].saturating_add(1),
+                    Stencil::Decrement(_) => self.stencil[stencil_index] = self.stencil[stencil_index].saturating_sub(1),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// twice the signed area of abc
+fn edge(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+// same as edge() but exactly negated for the reversed edge (both triangles
+// of a quad get the same number), otherwise rounding could drop pixels on the diagonal
+fn shared_edge(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    if (a.1, a.0) > (b.1, b.0) {
+        -edge(b, a, c)
+    } else {
+        edge(a, b, c)
+    }
+}
+
+fn covers(w: f32, a: (f32, f32), b: (f32, f32)) -> bool {
+    if w != 0. {
+        return w > 0.;
+    }
+
+    // exactly on the edge, only top & left edges own their pixels
+    let top = a.1 == b.1 && b.0 > a.0;
+    let left = b.1 < a.1;
+
+    top || left
+}
+
+// the same as ROUND_RECT_COMMON_VS, all radii are scaled down by the same factor
+// if some of them don't fit, returns (x, y) of tl, tr, br, bl
+fn fit_radii(size: (f32, f32), r: [f32; 8]) -> [(f32, f32); 4] {
+    let fit = |len: f32, a: f32, b: f32| len / (a + b).max(len);
+    let f = fit(size.0, r[0], r[1]).min(fit(size.0, r[3], r[2])).min(fit(size.1, r[4], r[7])).min(fit(size.1, r[5], r[6]));
+
+    [(r[0] * f, r[4] * f), (r[1] * f, r[5] * f), (r[2] * f, r[6] * f), (r[3] * f, r[7] * f)]
+}
+
+// the same as in ROUND_RECT_COMMON_FS, p is in pixels relative to the center (y goes up)
+fn round_rect_distance(p: (f32, f32), half_size: (f32, f32), radii: [(f32, f32); 4]) -> f32 {
+    let r = match (p.0 < 0., p.1 > 0.) {
+        (true, true) => radii[0],
+        (false, true) => radii[1],
+        (false, false) => radii[2],
+        (true, false) => radii[3],
+    };
+
+    let d = (p.0.abs() - half_size.0, p.1.abs() - half_size.1);
+    let q = (d.0 + r.0, d.1 + r.1);
+
+    if r.0 > 0. && r.1 > 0. && q.0 > 0. && q.1 > 0. {
+        let n = (q.0 / r.0, q.1 / r.1);
+
+        return (n.0 * n.0 + n.1 * n.1 - 1.) / (2. * (n.0 / r.0).hypot(n.1 / r.1));
+    }
+
+    d.0.max(0.).hypot(d.1.max(0.)) + d.0.max(d.1).min(0.)
+}
+
+// pixels relative to the center of bounds (a, b)
+fn local(pos: Pos, bounds: [f32; 4], scale: Pos) -> [f32; 4] {
+    let center = ((bounds[0] + bounds[2]) / 2., (bounds[1] + bounds[3]) / 2.);
+
+    [(pos.0 - center.0) / scale.0, (pos.1 - center.1) / scale.1, 0., 0.]
+}
+
+// of bounds (a, b), in pixels
+fn size(bounds: [f32; 4], scale: Pos) -> (f32, f32) {
+    ((bounds[2] - bounds[0]).abs() / scale.0, (bounds[3] - bounds[1]).abs() / scale.1)
+}
+
+// the same as BORDER_VS, (position, pixels relative to the center)
+fn border_vertex(v: &Vertex<BorderEdge>, scale: Pos) -> (Pos, [f32; 4]) {
+    let Vertex(corner, data) = v;
+    let b = data.bounds;
+    let (lo, hi) = ((b[0].min(b[2]), b[1].min(b[3])), (b[0].max(b[2]), b[1].max(b[3])));
+    let r = fit_radii(size(b, scale), data.radii);
+    let side = data.side[0] as usize;
+
+    let band = data.widths[side].max(match side {
+        0 => r[0].1.max(r[1].1),
+        1 => r[1].0.max(r[2].0),
+        2 => r[2].1.max(r[3].1),
+        _ => r[0].0.max(r[3].0),
+    });
+    let rest = ((hi.0 - lo.0 - band * scale.0).max(0.), (hi.1 - lo.1 - band * scale.1).max(0.));
+
+    // shrink the rect from the opposite side
+    let (lo_, hi_) = match side {
+        0 => ((lo.0, lo.1 + rest.1), hi),
+        1 => ((lo.0 + rest.0, lo.1), hi),
+        2 => (lo, (hi.0, hi.1 - rest.1)),
+        _ => (lo, (hi.0 - rest.0, hi.1)),
+    };
+    let pos = Pos(lo_.0 + (hi_.0 - lo_.0) * corner.0, lo_.1 + (hi_.1 - lo_.1) * corner.1);
+
+    (pos, local(pos, b, scale))
+}
+
+// the same as BORDER_FS
+fn border_coverage(p: (f32, f32), data: &BorderEdge, scale: Pos) -> f32 {
+    let size = size(data.bounds, scale);
+    let half_size = (size.0 / 2., size.1 / 2.);
+    let r = fit_radii(size, data.radii);
+    let w = data.widths;
+    let (side, style) = (data.side[0] as usize, data.side[1]);
+
+    // distances from the outer edges relative to the widths, the smallest one wins
+    let d = [half_size.1 - p.1, half_size.0 - p.0, half_size.1 + p.1, half_size.0 + p.0];
+    let n = [0, 1, 2, 3].map(|i| d[i] / w[i].max(0.001) + i as f32 * 0.0001);
+
+    if (0..4).any(|i| i != side && n[i] < n[side]) {
+        return 0.;
+    }
+
+    let inner_center = ((w[3] - w[1]) / 2., (w[2] - w[0]) / 2.);
+    let inner_half_size = (half_size.0 - (w[3] + w[1]) / 2., half_size.1 - (w[0] + w[2]) / 2.);
+    let inner_r = [(w[3], w[0]), (w[1], w[0]), (w[1], w[2]), (w[3], w[2])];
+    let inner_r = [0, 1, 2, 3].map(|i| ((r[i].0 - inner_r[i].0).max(0.), (r[i].1 - inner_r[i].1).max(0.)));
+
+    let outer = round_rect_distance(p, half_size, r);
+    let inner = round_rect_distance((p.0 - inner_center.0, p.1 - inner_center.1), inner_half_size, inner_r);
+    let clamp = |v: f32| v.clamp(0., 1.);
+    let coverage = clamp(0.5 - outer) * clamp(0.5 + inner);
+
+    let width = w[side].max(0.001);
+    let s = if side % 2 == 0 { p.0 } else { p.1 };
+
+    coverage * match style {
+        // double
+        3 => (clamp(width / 3. + 0.5 + outer) + clamp(width / 3. + 0.5 - inner)).min(1.),
+        // dotted
+        2 => {
+            let q = (s + width).rem_euclid(2. * width) - width;
+
+            clamp(0.5 + width / 2. - q.hypot(outer + width / 2.))
+        }
+        // dashed
+        1 => {
+            let q = (s + 2.5 * width).rem_euclid(5. * width) - 2.5 * width;
+
+            clamp(0.5 + 1.5 * width - q.abs())
+        }
+        _ => 1.,
+    }
+}
+
+// the same as BOX_SHADOW_VS, (position, pixels relative to the center)
+fn box_shadow_vertex(v: &Vertex<Shadow>, scale: Pos) -> (Pos, [f32; 4]) {
+    let Vertex(corner, data) = v;
+    let b = data.bounds;
+    let (lo, hi) = ((b[0].min(b[2]), b[1].min(b[3])), (b[0].max(b[2]), b[1].max(b[3])));
+    let [offset_x, offset_y, blur, spread] = data.params;
+
+    let sigma = (blur / 2.).max(0.25);
+    let margin = spread + 3. * sigma;
+    let (lo_, hi_) = match data.inset[0] {
+        0 => (
+            (lo.0 + (offset_x - margin) * scale.0, lo.1 + (offset_y - margin) * scale.1),
+            (hi.0 + (offset_x + margin) * scale.0, hi.1 + (offset_y + margin) * scale.1),
+        ),
+        _ => (lo, hi),
+    };
+    let pos = Pos(lo_.0 + (hi_.0 - lo_.0) * corner.0, lo_.1 + (hi_.1 - lo_.1) * corner.1);
+
+    (pos, local(pos, b, scale))
+}
+
+// the same as BOX_SHADOW_FS
+fn box_shadow_coverage(p: (f32, f32), data: &Shadow, scale: Pos) -> f32 {
+    let size = size(data.bounds, scale);
+    let half_size = (size.0 / 2., size.1 / 2.);
+    let r = fit_radii(size, data.radii);
+    let [offset_x, offset_y, blur, spread] = data.params;
+    let (offset, sigma, inset) = ((offset_x, offset_y), (blur / 2.).max(0.25), data.inset[0] != 0);
+
+    let box_ = (0.5 - round_rect_distance(p, half_size, r)).clamp(0., 1.);
+
+    let spread = if inset { -spread } else { spread };
+    let grow = |r: f32| if r > 0. { (r + spread).max(0.) } else { 0. };
+    let r = r.map(|r| (grow(r.0), grow(r.1)));
+    let value = blurred_round_rect((p.0 - offset.0, p.1 - offset.1), (half_size.0 + spread, half_size.1 + spread), r, sigma);
+
+    if inset {
+        box_ * (1. - value)
+    } else {
+        value * (1. - box_)
+    }
+}
+
+// the same as shadow() in BOX_SHADOW_FS
+fn blurred_round_rect(p: (f32, f32), half_size: (f32, f32), r: [(f32, f32); 4], sigma: f32) -> f32 {
+    if half_size.0 <= 0. || half_size.1 <= 0. {
+        return 0.;
+    }
+
+    let gaussian = |x: f32| (-(x * x) / (2. * sigma * sigma)).exp() / (2.5066283 * sigma);
+    let erf = |x: f32| {
+        let a = x.abs();
+        let t = 1. + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+        let t = t * t;
+
+        x.signum() - x.signum() / (t * t)
+    };
+    let extent = |y: f32, r: (f32, f32)| {
+        let d = (y.abs() - (half_size.1 - r.1)).max(0.) / r.1.max(0.001);
+
+        half_size.0 - r.0 + r.0 * (1. - d * d).max(0.).sqrt()
+    };
+    let row = |x: f32, y: f32| {
+        let (left, right) = if y > 0. { (extent(y, r[0]), extent(y, r[1])) } else { (extent(y, r[3]), extent(y, r[2])) };
+
+        (0.5 * (erf((x + left) * FRAC_1_SQRT_2 / sigma) - erf((x - right) * FRAC_1_SQRT_2 / sigma))).max(0.)
+    };
+
+    let start = (-3. * sigma).clamp(p.1 - half_size.1, p.1 + half_size.1);
+    let end = (3. * sigma).clamp(p.1 - half_size.1, p.1 + half_size.1);
+    let dy = (end - start) / 4.;
+
+    (0..4).map(|i| start + dy * (i as f32 + 0.5)).map(|y| row(p.0, p.1 - y) * gaussian(y) * dy).sum()
+}
+
+// the same as in GRADIENT_FS (p is relative to the center), with the extend mode
+fn gradient_t(p: (f32, f32), data: &GradientData) -> f32 {
+    let g = data.geometry;
+    let p = (p.0 - g[0], p.1 - g[1]);
+
+    let t = match data.flags[0] {
+        0 => {
+            let d = (g[2] - g[0], g[3] - g[1]);
+
+            (p.0 * d.0 + p.1 * d.1) / (d.0 * d.0 + d.1 * d.1).max(0.0001)
+        }
+        1 => (p.0 / g[2].max(0.0001)).hypot(p.1 / g[3].max(0.0001)),
+        _ => ((p.0.atan2(p.1) - g[2]) / TAU).rem_euclid(1.),
+    };
+
+    match data.flags[1] {
+        0 => t.clamp(0., 1.),
+        1 => t.rem_euclid(1.),
+        _ => 1. - (t.rem_euclid(2.) - 1.).abs(),
+    }
+}
+
+// buffers are just bytes here, the vertex is read back as it was uploaded
+fn read_vertex<T: Copy>(data: &[u8]) -> Vertex<T> {
+    assert!(data.len() >= mem::size_of::<Vertex<T>>());
+
+    unsafe { ptr::read_unaligned(data.as_ptr() as *const Vertex<T>) }
+}
+
+// like in GLSL
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+
+    t * t * (3. - 2. * t)
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v.clamp(0., 1.) * 255.).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn renderer(width: usize, height: usize) -> NotSureWhat<SoftwareBackend> {
+        NotSureWhat::with_backend(SoftwareBackend::new(width, height))
+    }
+
+    fn pixel(r: &NotSureWhat<SoftwareBackend>, x: usize, y: usize) -> RGBA {
+        r.backend().framebuffer.pixel(x, y)
+    }
+
+    #[test]
+    fn rect() {
+        let mut r = renderer(4, 4);
+        let rect = r.create_rect(Pos(-1., -1.), Pos(0., 0.), RGBA(0, 0, 255, 255));
+
+        r.set_display_list(&[DisplayItem::Rect(rect)]);
+        r.render();
+
+        // bottom-left quarter, color & alpha are / 256 (and alpha is blended too)
+        assert_eq!(pixel(&r, 0, 3), RGBA(1, 1, 254, 254));
+        assert_eq!(pixel(&r, 1, 2), RGBA(1, 1, 254, 254));
+        assert_eq!(pixel(&r, 2, 2), RGBA(255, 255, 255, 255));
+        assert_eq!(pixel(&r, 1, 1), RGBA(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn rect_edges() {
+        let mut r = renderer(4, 4);
+        let a = r.create_rect(Pos(-1., -1.), Pos(0., 1.), RGBA(0, 0, 0, 128));
+        let b = r.create_rect(Pos(0., -1.), Pos(1., 1.), RGBA(0, 0, 0, 128));
+
+        r.set_display_list(&[DisplayItem::Rect(a), DisplayItem::Rect(b)]);
+        r.render();
+
+        // shared edges (& diagonals) are not blended twice
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(pixel(&r, x, y), RGBA(128, 128, 128, 191), "{} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn translucent() {
+        let mut r = renderer(2, 2);
+        let red = r.create_rect(Pos(-1., -1.), Pos(1., 1.), RGBA(255, 0, 0, 255));
+        let black = r.create_rect(Pos(-1., -1.), Pos(1., 1.), RGBA(0, 0, 0, 128));
+
+        r.set_display_list(&[DisplayItem::Rect(red), DisplayItem::Rect(black)]);
+        r.render();
+
+        assert_eq!(pixel(&r, 0, 0), RGBA(127, 1, 1, 191));
+
+        // painter's order
+        r.set_display_list(&[DisplayItem::Rect(black), DisplayItem::Rect(red)]);
+        r.render();
+
+        assert_eq!(pixel(&r, 1, 1), RGBA(254, 1, 1, 254));
+    }
+
+    #[test]
+    fn image() {
+        let mut r = renderer(4, 4);
+        let pixels = [
+            255, 0, 0, 255, 0, 255, 0, 255,
+            0, 0, 255, 255, 0, 0, 0, 0,
+        ];
+        let image = r.create_image(Pos(-1., -1.), Pos(1., 1.), ImageData { width: 2, height: 2, stride: 8, pixels: &pixels });
+
+        r.set_display_list(&[DisplayItem::Image(image)]);
+        r.render();
+
+        // NEAREST, top row first
+        assert_eq!(pixel(&r, 0, 0), RGBA(255, 0, 0, 255));
+        assert_eq!(pixel(&r, 3, 1), RGBA(0, 255, 0, 255));
+        assert_eq!(pixel(&r, 1, 2), RGBA(0, 0, 255, 255));
+        assert_eq!(pixel(&r, 3, 3), RGBA(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn round_rect() {
+        let mut r = renderer(8, 8);
+        let radii = Radii { top_left: Pos(4., 4.), ..Radii::all(0.) };
+        let a = r.create_round_rect(Pos(-1., -1.), Pos(1., 1.), radii, RGBA(0, 0, 0, 255));
+
+        r.set_display_list(&[DisplayItem::RoundRect(a)]);
+        r.render();
+
+        // only the top-left corner is round
+        assert_eq!(pixel(&r, 0, 0), RGBA(255, 255, 255, 255));
+        assert_eq!(pixel(&r, 1, 1), RGBA(2, 2, 2, 253));
+        // antialiased
+        assert_eq!(pixel(&r, 1, 0), RGBA(202, 202, 202, 213));
+        assert_eq!(pixel(&r, 2, 0), RGBA(78, 78, 78, 201));
+        assert_eq!(pixel(&r, 4, 4), RGBA(1, 1, 1, 254));
+
+        for (x, y) in [(7, 0), (7, 7), (0, 7)] {
+            assert_eq!(pixel(&r, x, y), RGBA(1, 1, 1, 254), "{} {}", x, y);
+        }
+
+        // radii which don't fit are scaled down (to 4, 4)
+        let b = r.create_round_rect(Pos(-1., -1.), Pos(1., 1.), Radii::all(100.), RGBA(0, 0, 0, 255));
+
+        r.set_display_list(&[DisplayItem::RoundRect(b)]);
+        r.render();
+
+        for (x, y) in [(0, 0), (7, 0), (7, 7), (0, 7)] {
+            assert_eq!(pixel(&r, x, y), RGBA(255, 255, 255, 255), "{} {}", x, y);
+        }
+
+        assert_eq!(pixel(&r, 6, 6), RGBA(2, 2, 2, 253));
+    }
+
+    #[test]
+    fn border() {
+        let mut r = renderer(8, 8);
+        let side = |color| BorderSide::solid(1., color);
+        let border = Border {
+            top: side(RGBA(255, 0, 0, 255)),
+            right: side(RGBA(0, 255, 0, 255)),
+            bottom: side(RGBA(0, 0, 255, 255)),
+            left: BorderSide { width: 2., ..side(RGBA(0, 0, 0, 255)) },
+            radii: Radii::all(0.),
+        };
+        let a = r.create_border(Pos(-1., -1.), Pos(1., 1.), &border);
+
+        r.set_display_list(&[DisplayItem::Border(a)]);
+        r.render();
+
+        // corners are split on the diagonal (relative to the widths), ties go to the first side
+        assert_eq!(pixel(&r, 0, 0), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 4, 0), RGBA(254, 1, 1, 254));
+        assert_eq!(pixel(&r, 7, 0), RGBA(254, 1, 1, 254));
+        assert_eq!(pixel(&r, 7, 4), RGBA(1, 254, 1, 254));
+        assert_eq!(pixel(&r, 7, 7), RGBA(1, 254, 1, 254));
+        assert_eq!(pixel(&r, 4, 7), RGBA(1, 1, 254, 254));
+        assert_eq!(pixel(&r, 0, 7), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 0, 4), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 1, 4), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 1, 1), RGBA(1, 1, 1, 254));
+
+        // inside
+        assert_eq!(pixel(&r, 2, 4), RGBA(255, 255, 255, 255));
+        assert_eq!(pixel(&r, 6, 1), RGBA(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn border_styles() {
+        let mut r = renderer(16, 4);
+        let side = BorderSide { width: 2., color: RGBA(0, 0, 0, 255), style: BorderStyle::Dashed };
+        let a = r.create_border(Pos(-1., -1.), Pos(1., 1.), &Border::new(side, Radii::all(0.)));
+
+        r.set_display_list(&[DisplayItem::Border(a)]);
+        r.render();
+
+        // 6px dashes & 4px gaps, symmetric around the center of the side
+        let top: Vec<_> = (0..16).map(|x| pixel(&r, x, 0).0).collect();
+
+        assert_eq!(top, [1, 255, 255, 255, 255, 1, 1, 1, 1, 1, 1, 255, 255, 255, 255, 1]);
+    }
+
+    #[test]
+    fn box_shadow() {
+        let mut r = renderer(16, 16);
+        let shadow = BoxShadow { spread: 2., ..BoxShadow::new(Pos(0., 0.), 0., RGBA(0, 0, 0, 255)) };
+        let a = r.create_box_shadow(Pos(-0.5, -0.5), Pos(0.5, 0.5), &shadow);
+
+        r.set_display_list(&[DisplayItem::BoxShadow(a)]);
+        r.render();
+
+        // 2px around the box (the blur is just antialiasing), never under it
+        let row: Vec<_> = (0..16).map(|x| pixel(&r, x, 8).0).collect();
+
+        assert_eq!(row, [255, 249, 7, 1, 255, 255, 255, 255, 255, 255, 255, 255, 1, 7, 249, 255]);
+
+        // inside of the box, on the other side than the offset
+        let shadow = BoxShadow { inset: true, ..BoxShadow::new(Pos(2., 0.), 0., RGBA(0, 0, 0, 255)) };
+        let b = r.create_box_shadow(Pos(-0.5, -0.5), Pos(0.5, 0.5), &shadow);
+
+        r.set_display_list(&[DisplayItem::BoxShadow(b)]);
+        r.render();
+
+        let row: Vec<_> = (0..16).map(|x| pixel(&r, x, 8).0).collect();
+
+        assert_eq!(row, [255, 255, 255, 255, 1, 7, 249, 255, 255, 255, 255, 255, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn gradient() {
+        let mut r = renderer(16, 2);
+        let stops = vec![(0., RGBA(255, 0, 0, 255)), (1., RGBA(0, 0, 255, 255))];
+        let mut gradient = Gradient::new(GradientKind::Linear { start: Pos(-4., 0.), end: Pos(4., 0.) }, stops);
+        let a = r.create_gradient(Pos(-1., -1.), Pos(1., 1.), &gradient);
+
+        r.set_display_list(&[DisplayItem::Gradient(a)]);
+        r.render();
+
+        // red channel, from 4px left of the center to 4px right of it
+        let row: Vec<_> = (0..16).map(|x| pixel(&r, x, 0).0).collect();
+
+        assert_eq!(row, [255, 255, 255, 255, 239, 207, 175, 143, 112, 80, 48, 16, 0, 0, 0, 0]);
+
+        gradient.extend = Extend::Repeat;
+        let b = r.create_gradient(Pos(-1., -1.), Pos(1., 1.), &gradient);
+
+        r.set_display_list(&[DisplayItem::Gradient(b)]);
+        r.render();
+
+        let row: Vec<_> = (0..16).map(|x| pixel(&r, x, 0).0).collect();
+
+        assert_eq!(row, [112, 80, 48, 16, 239, 207, 175, 143, 112, 80, 48, 16, 239, 207, 175, 143]);
+
+        gradient.extend = Extend::Reflect;
+        let c = r.create_gradient(Pos(-1., -1.), Pos(1., 1.), &gradient);
+
+        r.set_display_list(&[DisplayItem::Gradient(c)]);
+        r.render();
+
+        let row: Vec<_> = (0..16).map(|x| pixel(&r, x, 0).0).collect();
+
+        assert_eq!(row, [143, 175, 207, 239, 239, 207, 175, 143, 112, 80, 48, 16, 16, 48, 80, 112]);
+    }
+
+    #[test]
+    fn conic_gradient() {
+        let mut r = renderer(4, 4);
+        let (red, blue) = (RGBA(255, 0, 0, 255), RGBA(0, 0, 255, 255));
+        let stops = vec![(0., red), (0.5, red), (0.5, blue), (1., blue)];
+        let gradient = Gradient::new(GradientKind::Conic { center: Pos(0., 0.), angle: 0. }, stops);
+        let a = r.create_gradient(Pos(-1., -1.), Pos(1., 1.), &gradient);
+
+        r.set_display_list(&[DisplayItem::Gradient(a)]);
+        r.render();
+
+        // clockwise from the top, hard stop in the middle
+        assert_eq!([pixel(&r, 0, 0), pixel(&r, 3, 0), pixel(&r, 3, 3), pixel(&r, 0, 3)], [blue, red, red, blue]);
+    }
+
+    #[test]
+    fn clip() {
+        let mut r = renderer(8, 8);
+        let a = r.create_rect(Pos(-1., -1.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+
+        // nested ones are intersected
+        r.set_display_list(&[
+            DisplayItem::PushClip(Clip::rect(Pos(-1., -1.), Pos(0., 0.))),
+            DisplayItem::PushClip(Clip::rect(Pos(1., 1.), Pos(-0.5, -1.))),
+            DisplayItem::Rect(a),
+            DisplayItem::PopClip,
+            DisplayItem::PopClip,
+        ]);
+        r.render();
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = (2..4).contains(&x) && (4..8).contains(&y);
+
+                assert_eq!(pixel(&r, x, y).0, if inside { 1 } else { 255 }, "{} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn round_clip() {
+        let mut r = renderer(8, 8);
+        let black = r.create_rect(Pos(-1., -1.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+        let blue = r.create_rect(Pos(-1., -1.), Pos(1., 1.), RGBA(0, 0, 255, 255));
+        let corner = r.create_rect(Pos(-1., -1.), Pos(-0.5, -0.5), RGBA(0, 0, 0, 255));
+
+        r.set_display_list(&[
+            DisplayItem::PushClip(Clip::round(Pos(-1., -1.), Pos(1., 1.), Radii::all(4.))),
+            DisplayItem::Rect(black),
+            DisplayItem::PushClip(Clip::rect(Pos(0., -1.), Pos(1., 1.))),
+            DisplayItem::Rect(blue),
+            DisplayItem::PopClip,
+            DisplayItem::PopClip,
+            DisplayItem::Rect(corner),
+        ]);
+        r.render();
+
+        // blue is on top of black
+        let (white, black, blue) = (RGBA(255, 255, 255, 255), RGBA(1, 1, 1, 254), RGBA(0, 0, 253, 254));
+
+        // no antialiasing, it's either in or out
+        assert_eq!([0, 1, 2, 3].map(|x| pixel(&r, x, 0)), [white, white, black, black]);
+        assert_eq!([4, 5, 6, 7].map(|x| pixel(&r, x, 0)), [blue, blue, white, white]);
+        assert_eq!(pixel(&r, 3, 4), black);
+        assert_eq!(pixel(&r, 4, 4), blue);
+        assert_eq!(pixel(&r, 7, 7), white);
+
+        // not clipped after the pop
+        assert_eq!(pixel(&r, 0, 7), black);
+    }
+
+    #[test]
+    fn removed() {
+        let mut r = renderer(32, 32);
+        let font = r.add_font(BoxGlyphs);
+        let glyphs = [PositionedGlyph { id: 0, x: 0., y: 0., font: 0 }];
+        let text = r.create_text(Pos(-1., 0.), font, 20., &glyphs, RGBA(0, 0, 0, 255));
+        let rect = r.create_rect(Pos(-1., -1.), Pos(1., 1.), RGBA(0, 0, 0, 255));
+
+        r.set_display_list(&[DisplayItem::Rect(rect), DisplayItem::Text(text)]);
+        r.remove_rect(rect);
+        r.remove_text(text);
+
+        // still in the display list but not drawn
+        r.render();
+
+        assert_eq!(pixel(&r, 5, 10), RGBA(255, 255, 255, 255));
+
+        // & the slot can be reused
+        let text = r.create_text(Pos(-1., 0.), font, 20., &glyphs, RGBA(0, 0, 0, 255));
+
+        r.render();
+
+        assert_eq!(pixel(&r, 5, 10), RGBA(255, 255, 255, 255));
+
+        r.set_display_list(&[DisplayItem::Text(text)]);
+        r.render();
+
+        assert_eq!(pixel(&r, 5, 10), RGBA(1, 1, 1, 254));
+    }
+
+    #[test]
+    fn box_glyphs() {
+        let mut r = renderer(32, 32);
+        let font = r.add_font(BoxGlyphs);
+        let glyphs = [0, 1].map(|i| PositionedGlyph { id: i, x: i as f32 * 12., y: 0., font: 0 });
+        let text = r.create_text(Pos(-1., 0.), font, 20., &glyphs, RGBA(0, 0, 0, 255));
+
+        r.set_display_list(&[DisplayItem::Text(text)]);
+        r.render();
+
+        // 10x14 boxes on the baseline (row 16)
+        assert_eq!(pixel(&r, 0, 2), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 9, 15), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 0, 1), RGBA(255, 255, 255, 255));
+        assert_eq!(pixel(&r, 0, 16), RGBA(255, 255, 255, 255));
+        assert_eq!(pixel(&r, 10, 8), RGBA(255, 255, 255, 255));
+        assert_eq!(pixel(&r, 12, 8), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 21, 8), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 22, 8), RGBA(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn spans() {
+        let mut r = renderer(32, 32);
+        let font = r.add_font(BoxGlyphs);
+        let glyphs = [PositionedGlyph { id: 0, x: 0., y: 0., font: 0 }];
+        let spans = [TextSpan::new(font, 20., &glyphs, 10.75, RGBA(0, 0, 0, 255)), TextSpan::new(font, 20., &glyphs, 10., RGBA(0, 0, 0, 255))];
+        let text = r.create_rich_text(Pos(-1., 0.), &spans);
+
+        r.set_display_list(&[DisplayItem::Text(text)]);
+        r.render();
+
+        // the second box is on a whole pixel, its fraction would be in the glyph
+        assert_eq!(pixel(&r, 9, 8), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 10, 8), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 19, 8), RGBA(1, 1, 1, 254));
+        assert_eq!(pixel(&r, 20, 8), RGBA(255, 255, 255, 255));
+    }
+}
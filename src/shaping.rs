@@ -0,0 +1,370 @@
+// UTF-8 -> positioned glyphs
+//
+// this is where kerning (GPOS/kern), ligatures (GSUB) & mark positioning
+// happen, all of it is done by rustybuzz (harfbuzz port), we just scale
+// the result to pixels & keep the clusters for the layout
+//
+// text is split into bidi runs (UAX #9, levels & reordering by unicode-bidi)
+// and these are split by script, each piece is then shaped on its own with
+// the right direction (rustybuzz mirrors brackets in rtl) & put in visual order
+//
+// if the font has fallbacks, script runs are split once more by the first
+// font which has the chars (marks & joiners stay with their base) so that
+// every piece is shaped with the font it's going to be rendered with
+
+use std::ops::Range;
+
+use unicode_bidi::{BidiInfo, Level};
+use unicode_script::{Script, UnicodeScript};
+
+use super::font::Font;
+use super::glyphs::{GlyphId, PositionedGlyph};
+
+/// One shaped glyph, in pixels, relative to the start of the text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShapedGlyph {
+    pub id: GlyphId,
+    /// pen position (offsets included), y goes up
+    pub x: f32,
+    pub y: f32,
+    pub advance: f32,
+    /// byte offset of the first char this glyph came from
+    /// (ligatures have one glyph for more chars, marks share the base cluster)
+    pub cluster: usize,
+    /// from rtl run, so the cluster goes from right to left
+    pub rtl: bool,
+    /// in the fallback chain, 0 is the font itself
+    pub font: usize,
+}
+
+/// Glyphs in visual order (left to right), clusters are still logical
+#[derive(Clone, Debug, Default)]
+pub struct ShapedText {
+    pub glyphs: Vec<ShapedGlyph>,
+    /// sum of all advances
+    pub width: f32,
+}
+
+impl ShapedText {
+    /// what `create_text()` needs
+    pub fn positioned(&self) -> Vec<PositionedGlyph> {
+        self.glyphs.iter().map(|g| PositionedGlyph { id: g.id, x: g.x, y: g.y, font: g.font }).collect()
+    }
+}
+
+impl Font {
+    /// paragraph direction is taken from the first strong char
+    pub fn shape(&self, text: &str, size: f32) -> ShapedText {
+        self.shape_with_level(text, size, None)
+    }
+
+    /// with explicit paragraph level (ltr/rtl)
+    pub(crate) fn shape_with_level(&self, text: &str, size: f32, level: Option<Level>) -> ShapedText {
+        let bidi = BidiInfo::new(text, level);
+
+        let mut pen = (0., 0.);
+        let mut glyphs = Vec::with_capacity(text.len());
+
+        for para in &bidi.paragraphs {
+            let (levels, runs) = bidi.visual_runs(para, para.range.clone());
+
+            for run in runs {
+                let rtl = levels[run.start].is_rtl();
+                let mut pieces: Vec<_> = script_runs(text, run).into_iter().flat_map(|r| self.font_runs(text, r)).collect();
+
+                // rtl run is going right to left (script & font runs too)
+                if rtl {
+                    pieces.reverse();
+                }
+
+                for (i, range) in pieces {
+                    let font = self.chain().nth(i).unwrap();
+                    let scale = font.scale(size);
+                    let mut buffer = rustybuzz::UnicodeBuffer::new();
+
+                    buffer.push_str(&text[range.clone()]);
+                    buffer.set_direction(if rtl { rustybuzz::Direction::RightToLeft } else { rustybuzz::Direction::LeftToRight });
+
+                    // default features, so kern, liga, mark, mkmk, ...
+                    let output = rustybuzz::shape(font.shaper(), &[], buffer);
+
+                    for (info, pos) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+                        glyphs.push(ShapedGlyph {
+                            id: info.glyph_id as GlyphId,
+                            x: pen.0 + pos.x_offset as f32 * scale,
+                            y: pen.1 + pos.y_offset as f32 * scale,
+                            advance: pos.x_advance as f32 * scale,
+                            cluster: range.start + info.cluster as usize,
+                            rtl,
+                            font: i,
+                        });
+
+                        pen.0 += pos.x_advance as f32 * scale;
+                        pen.1 += pos.y_advance as f32 * scale;
+                    }
+                }
+            }
+        }
+
+        ShapedText { glyphs, width: pen.0 }
+    }
+}
+
+impl Font {
+    // (index in the chain, range)
+    fn font_runs(&self, text: &str, range: Range<usize>) -> Vec<(usize, Range<usize>)> {
+        if self.fallback.is_empty() {
+            return vec![(0, range)];
+        }
+
+        font_runs(text, range, |ch| self.chain().position(|f| f.glyph_index(ch).is_some()))
+    }
+}
+
+// `font` is the first one which has the char, chars which no font has stay in the current run
+fn font_runs(text: &str, range: Range<usize>, font: impl Fn(char) -> Option<usize>) -> Vec<(usize, Range<usize>)> {
+    let mut runs = Vec::new();
+    let mut start = range.start;
+    let mut current = 0;
+
+    for (i, ch) in text[range.clone()].char_indices() {
+        // marks, ZWJ & variation selectors (emoji sequences)
+        if ch.script() == Script::Inherited {
+            continue;
+        }
+
+        let font = font(ch).unwrap_or(current);
+
+        if font != current && range.start + i > start {
+            runs.push((current, start..range.start + i));
+            start = range.start + i;
+        }
+
+        current = font;
+    }
+
+    runs.push((current, start..range.end));
+
+    runs
+}
+
+// common chars (spaces, punctuation, digits) & marks go with the preceding script
+fn script_runs(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = range.start;
+    let mut current = None;
+
+    for (i, ch) in text[range.clone()].char_indices() {
+        let script = ch.script();
+
+        if matches!(script, Script::Common | Script::Inherited | Script::Unknown) {
+            continue;
+        }
+
+        if matches!(current, Some(s) if s != script) {
+            runs.push(start..range.start + i);
+            start = range.start + i;
+        }
+
+        current = Some(script);
+    }
+
+    runs.push(start..range.end);
+
+    runs
+}
+
+/// Size of a single line of text in pixels, see `Font::layout()` for paragraphs
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextMetrics {
+    pub width: f32,
+    pub ascent: f32,
+    /// negative, below the baseline
+    pub descent: f32,
+    pub line_gap: f32,
+    /// (byte offset, x) of the caret before every char & at the end
+    pub carets: Vec<(usize, f32)>,
+}
+
+impl Font {
+    /// needs no renderer (nor GL), `Font` can be sent to another thread
+    pub fn measure(&self, text: &str, size: f32) -> TextMetrics {
+        let shaped = self.shape(text, size);
+        let metrics = self.metrics(size);
+
+        TextMetrics {
+            width: shaped.width,
+            ascent: metrics.ascent,
+            descent: metrics.descent,
+            line_gap: metrics.line_gap,
+            carets: shaped.carets(text),
+        }
+    }
+}
+
+impl ShapedText {
+    /// see `TextMetrics::carets`, ligatures are split evenly between their chars,
+    /// carets are on the leading edge of the char (right one for rtl chars)
+    pub fn carets(&self, text: &str) -> Vec<(usize, f32)> {
+        let mut clusters = self.clusters();
+
+        clusters.sort_by_key(|c| c.cluster);
+
+        let mut carets = Vec::new();
+
+        for (i, c) in clusters.iter().enumerate() {
+            let end = clusters.get(i + 1).map_or(text.len(), |c| c.cluster);
+            let count = text[c.cluster..end].chars().count();
+
+            for (k, (offset, _)) in text[c.cluster..end].char_indices().enumerate() {
+                let d = c.advance * k as f32 / count as f32;
+
+                carets.push((c.cluster + offset, if c.rtl { c.x + c.advance - d } else { c.x + d }));
+            }
+        }
+
+        // after the last char (trailing edge)
+        let end = match clusters.last() {
+            Some(c) if c.rtl => c.x,
+            Some(c) => c.x + c.advance,
+            None => 0.,
+        };
+
+        carets.push((text.len(), end));
+
+        carets
+    }
+
+    /// byte offset of the caret closest to x (for clicks)
+    pub fn offset_at(&self, text: &str, x: f32) -> usize {
+        self.carets(text)
+            .into_iter()
+            .min_by(|a, b| (a.1 - x).abs().partial_cmp(&(b.1 - x).abs()).unwrap())
+            .map_or(0, |(offset, _)| offset)
+    }
+
+    /// what has to be highlighted for the (logical) selection, from left to right,
+    /// it can be more than one span if the selection crosses a direction change
+    pub fn selection(&self, range: Range<usize>) -> Vec<Range<f32>> {
+        let mut spans: Vec<Range<f32>> = Vec::new();
+
+        for c in self.clusters() {
+            if !range.contains(&c.cluster) {
+                continue;
+            }
+
+            match spans.last_mut() {
+                Some(span) if span.end == c.x => span.end = c.x + c.advance,
+                _ => spans.push(c.x..c.x + c.advance),
+            }
+        }
+
+        spans
+    }
+
+    // glyphs of the same cluster together, in visual order
+    fn clusters(&self) -> Vec<Cluster> {
+        let mut clusters: Vec<Cluster> = Vec::new();
+        let mut pen = 0.;
+
+        for g in &self.glyphs {
+            match clusters.last_mut() {
+                Some(c) if c.cluster == g.cluster => c.advance += g.advance,
+                _ => clusters.push(Cluster { cluster: g.cluster, x: pen, advance: g.advance, rtl: g.rtl }),
+            }
+
+            pen += g.advance;
+        }
+
+        clusters
+    }
+}
+
+struct Cluster {
+    cluster: usize,
+    x: f32,
+    advance: f32,
+    rtl: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap_font::tests::font;
+
+    // 8px per char, hebrew is 2 bytes per char
+    const TEXT: &str = "ab \u{5d0}\u{5d1}";
+
+    fn clusters(shaped: &ShapedText) -> Vec<(usize, bool)> {
+        shaped.glyphs.iter().map(|g| (g.cluster, g.rtl)).collect()
+    }
+
+    #[test]
+    fn bidi() {
+        let font = font("ab \u{5d0}\u{5d1}");
+
+        // rtl run is reversed, in visual order
+        let shaped = font.shape(TEXT, 10.);
+
+        assert_eq!(clusters(&shaped), [(0, false), (1, false), (2, false), (5, true), (3, true)]);
+        assert_eq!(shaped.glyphs.iter().map(|g| g.x).collect::<Vec<_>>(), [0., 8., 16., 24., 32.]);
+        assert_eq!(shaped.width, 40.);
+
+        // rtl paragraph, so the runs are reversed too
+        let shaped = font.shape_with_level(TEXT, 10., Some(Level::rtl()));
+
+        assert_eq!(clusters(&shaped), [(5, true), (3, true), (2, true), (0, false), (1, false)]);
+    }
+
+    #[test]
+    fn carets() {
+        let shaped = font("ab \u{5d0}\u{5d1}").shape(TEXT, 10.);
+
+        // rtl chars have the caret on their right edge, the end is after the last logical char
+        assert_eq!(shaped.carets(TEXT), [(0, 0.), (1, 8.), (2, 16.), (3, 40.), (5, 32.), (7, 24.)]);
+        assert_eq!(font("ab").shape("", 10.).carets(""), [(0, 0.)]);
+    }
+
+    #[test]
+    fn offset_at() {
+        let shaped = font("ab \u{5d0}\u{5d1}").shape(TEXT, 10.);
+
+        assert_eq!(shaped.offset_at(TEXT, -5.), 0);
+        assert_eq!(shaped.offset_at(TEXT, 9.), 1);
+        assert_eq!(shaped.offset_at(TEXT, 39.), 3);
+        assert_eq!(shaped.offset_at(TEXT, 25.), 7);
+    }
+
+    #[test]
+    fn selection() {
+        let shaped = font("ab \u{5d0}\u{5d1}").shape(TEXT, 10.);
+
+        // logical range, it's split where the direction changes
+        assert_eq!(shaped.selection(1..5), [8. ..24., 32. ..40.]);
+        assert_eq!(shaped.selection(0..TEXT.len()), [0. ..40.]);
+        assert_eq!(shaped.selection(0..0), []);
+    }
+
+    #[test]
+    fn scripts() {
+        // spaces, digits & marks go with the preceding script
+        let text = "ab 1 \u{430}\u{301}\u{431} c";
+
+        assert_eq!(script_runs(text, 0..text.len()), [0..5, 5..12, 12..13]);
+        assert_eq!(script_runs(text, 5..12), [Range { start: 5, end: 12 }]);
+    }
+
+    #[test]
+    fn fallback_runs() {
+        let text = "ab\u{1f600}\u{fe0f}c?";
+        // emoji in the second font, the third one has nothing
+        let font = |ch: char| match ch {
+            '\u{1f600}' => Some(1),
+            '?' => None,
+            _ => Some(0),
+        };
+
+        // the variation selector stays with the emoji & missing char with the current run
+        assert_eq!(font_runs(text, 0..text.len(), font), [(0, 0..2), (1, 2..9), (0, 9..11)]);
+    }
+}